@@ -1,10 +1,14 @@
-use wee_http::{Request, Response, Server};
+use std::error::Error;
+use wee_http::{Request, Response, Server, StatusCode};
 
-fn main() {
-    Server::bind("0.0.0.0:8080").path("/", root).listen()
+fn main() -> Result<(), Box<dyn Error>> {
+    Server::bind("0.0.0.0:8080")?.path("/", root).listen()?;
+    Ok(())
 }
 
-fn root(req: Request) -> Response {
-    let res = Response::new();
-    res
+fn root(_req: Request) -> Response {
+    Response::new()
+        .status(StatusCode::Ok)
+        .header("Content-Type", "text/plain")
+        .body("Hello from wee-http")
 }
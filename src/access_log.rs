@@ -0,0 +1,67 @@
+//! Access logs in the Common Log Format, see
+//! [`crate::ServerBuilder::access_log`]
+
+use crate::{Request, StatusCode};
+use std::{io::Write, sync::Mutex, time::SystemTime};
+
+/// Where access log lines go, shared by every connection
+pub(crate) struct AccessLog(Mutex<Box<dyn Write + Send>>);
+
+impl AccessLog {
+    pub(crate) fn new(writer: impl Write + Send + 'static) -> Self {
+        Self(Mutex::new(Box::new(writer)))
+    }
+
+    /// Write the line for `entry` answered with `status_code` and a body of
+    /// `bytes`, write errors are logged rather than failing the response
+    pub(crate) fn write(
+        &self,
+        entry: &Entry,
+        status_code: StatusCode,
+        bytes: usize,
+    ) {
+        let line = entry.line(status_code, bytes);
+        let mut writer = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        if let Err(err) = writer
+            .write_all(line.as_bytes())
+            .and_then(|()| writer.flush())
+        {
+            log::warn!("failed to write access log: {err}");
+        }
+    }
+}
+
+/// What is logged about a request, taken before the handler consumes it
+pub(crate) struct Entry {
+    client: String,
+    time: SystemTime,
+    request_line: String,
+}
+
+impl Entry {
+    pub(crate) fn new(request: &Request) -> Self {
+        Self {
+            client: request
+                .client_ip()
+                .map_or_else(|| "-".into(), |ip| ip.to_string()),
+            time: SystemTime::now(),
+            request_line: request.request_line().into(),
+        }
+    }
+
+    /// `host ident authuser [date] "request line" status bytes`, with `-`
+    /// for the fields that aren't known and an empty body
+    fn line(&self, status_code: StatusCode, bytes: usize) -> String {
+        let bytes = match bytes {
+            0 => "-".to_string(),
+            bytes => bytes.to_string(),
+        };
+        format!(
+            "{} - - [{}] \"{}\" {} {bytes}\n",
+            self.client,
+            crate::date::common_log_date(self.time),
+            self.request_line,
+            status_code.as_u16()
+        )
+    }
+}
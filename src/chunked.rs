@@ -0,0 +1,160 @@
+//! `Transfer-Encoding: chunked` framing
+
+use crate::Error;
+
+/// The longest chunk size line accepted, including any extensions
+const MAX_SIZE_LINE: usize = 1024;
+
+/// The most bytes of trailer headers accepted after the final chunk
+const MAX_TRAILERS: usize = 16 * 1024;
+
+/// Decodes a chunked body as it arrives
+pub(crate) struct ChunkedDecoder {
+    max_body: usize,
+    consumed: usize,
+    body: Vec<u8>,
+}
+
+impl ChunkedDecoder {
+    pub(crate) fn new(max_body: usize) -> Self {
+        Self {
+            max_body,
+            consumed: 0,
+            body: Vec::new(),
+        }
+    }
+
+    /// Decode every complete chunk in `data`, returning whether the final
+    /// chunk has been seen. `data` must hold the body from its first byte and
+    /// only grow between calls.
+    pub(crate) fn decode(&mut self, data: &[u8]) -> Result<bool, Error> {
+        loop {
+            let rest = &data[self.consumed..];
+            let Some(line_len) = find_crlf(rest) else {
+                if rest.len() > MAX_SIZE_LINE {
+                    return Err(Error::Malformed);
+                }
+                return Ok(false);
+            };
+
+            let size_line = std::str::from_utf8(&rest[..line_len])
+                .map_err(|_| Error::Malformed)?;
+            let size = size_line.split(';').next().unwrap_or_default().trim();
+            let size = usize::from_str_radix(size, 16)
+                .map_err(|_| Error::Malformed)?;
+            let data_start = line_len + 2;
+
+            if size == 0 {
+                return self.trailers(&rest[data_start..], data_start);
+            }
+
+            if self.body.len().saturating_add(size) > self.max_body {
+                return Err(Error::PayloadTooLarge);
+            }
+            // Without a limit a size near `usize::MAX` still has to fail
+            let data_end = data_start
+                .checked_add(size)
+                .filter(|end| end.checked_add(2).is_some())
+                .ok_or(Error::Malformed)?;
+            if rest.len() < data_end + 2 {
+                return Ok(false);
+            }
+            if &rest[data_end..data_end + 2] != b"\r\n" {
+                return Err(Error::Malformed);
+            }
+
+            self.body.extend_from_slice(&rest[data_start..data_end]);
+            self.consumed += data_end + 2;
+        }
+    }
+
+    /// Skip the trailer section after the final chunk, `offset` is how far
+    /// `trailers` starts from the unconsumed data
+    fn trailers(
+        &mut self,
+        trailers: &[u8],
+        offset: usize,
+    ) -> Result<bool, Error> {
+        // The common case of no trailers is just the CRLF ending the body
+        let len = if trailers.starts_with(b"\r\n") {
+            Some(2)
+        } else {
+            trailers
+                .windows(4)
+                .position(|window| window == b"\r\n\r\n")
+                .map(|position| position + 4)
+        };
+
+        match len {
+            Some(len) => {
+                self.consumed += offset + len;
+                Ok(true)
+            }
+            None if trailers.len() > MAX_TRAILERS => Err(Error::Malformed),
+            None => Ok(false),
+        }
+    }
+
+    /// How many bytes of the data the body took up, once decoded
+    pub(crate) fn consumed(&self) -> usize {
+        self.consumed
+    }
+
+    pub(crate) fn into_body(self) -> Vec<u8> {
+        self.body
+    }
+}
+
+fn find_crlf(data: &[u8]) -> Option<usize> {
+    data.windows(2).position(|window| window == b"\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_two_chunks() {
+        let data = b"5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\n\r\nnext";
+        let mut decoder = ChunkedDecoder::new(1024);
+        assert!(decoder.decode(data).unwrap());
+        assert_eq!(decoder.consumed, data.len() - 4);
+        assert_eq!(decoder.into_body(), b"hello, world");
+    }
+
+    #[test]
+    fn decode_incrementally() {
+        let data = b"5\r\nhello\r\n0\r\nExpires: never\r\n\r\n";
+        let mut decoder = ChunkedDecoder::new(1024);
+        for len in 0..data.len() {
+            assert!(!decoder.decode(&data[..len]).unwrap());
+        }
+        assert!(decoder.decode(data).unwrap());
+        assert_eq!(decoder.consumed, data.len());
+        assert_eq!(decoder.into_body(), b"hello");
+    }
+
+    #[test]
+    fn decode_malformed() {
+        let mut decoder = ChunkedDecoder::new(1024);
+        assert_eq!(decoder.decode(b"zz\r\nhello\r\n"), Err(Error::Malformed));
+
+        let mut decoder = ChunkedDecoder::new(1024);
+        assert_eq!(decoder.decode(b"2\r\nhello\r\n"), Err(Error::Malformed));
+
+        for size in ["ffffffffffffffff", "fffffffffffffffd"] {
+            let mut decoder = ChunkedDecoder::new(usize::MAX);
+            let data = format!("{size}\r\nhello\r\n0\r\n\r\n");
+            assert_eq!(decoder.decode(data.as_bytes()), Err(Error::Malformed));
+        }
+    }
+
+    #[test]
+    fn decode_too_large() {
+        let mut decoder = ChunkedDecoder::new(4);
+        assert_eq!(
+            decoder.decode(b"5\r\nhello\r\n0\r\n\r\n"),
+            Err(Error::PayloadTooLarge)
+        );
+    }
+}
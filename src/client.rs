@@ -0,0 +1,198 @@
+use crate::{Method, Response};
+use std::{
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+/// How long a request can take unless configured otherwise
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A minimal HTTP/1.1 client for plain `http://` URLs, for tests and simple
+/// integrations. Every request opens a new connection and reads the whole
+/// response before returning it.
+#[derive(Debug, Clone)]
+pub struct Client {
+    headers: Vec<(String, String)>,
+    timeout: Option<Duration>,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Client {
+    pub fn new() -> Self {
+        Self {
+            headers: Vec::new(),
+            timeout: Some(DEFAULT_TIMEOUT),
+        }
+    }
+
+    /// Send the header `key` with every request, a `Host` set here replaces
+    /// the one taken from the URL
+    pub fn header(mut self, key: impl ToString, value: impl ToString) -> Self {
+        self.headers.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// How long to wait for each step of a request, connecting, sending and
+    /// reading the response, defaults to 30 seconds. `None` waits forever.
+    pub fn timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.timeout = timeout.into();
+        self
+    }
+
+    /// `GET` the resource at `url`
+    pub fn get(&self, url: &str) -> io::Result<Response> {
+        self.request(Method::Get, url, Vec::new())
+    }
+
+    /// Send a `method` request with `body` to `url`, such as
+    /// `http://127.0.0.1:8080/users?page=2`. Fails with
+    /// [`io::ErrorKind::InvalidInput`] for URLs that aren't `http://` and
+    /// [`io::ErrorKind::InvalidData`] if the response can't be parsed.
+    pub fn request(
+        &self,
+        method: Method,
+        url: &str,
+        body: impl Into<Vec<u8>>,
+    ) -> io::Result<Response> {
+        let (authority, target) = split_url(url)?;
+        let body = body.into();
+
+        let method_str: &str = method.into();
+        let mut head = format!("{method_str} {target} HTTP/1.1\r\n");
+        let has_host = self
+            .headers
+            .iter()
+            .any(|(key, _)| key.eq_ignore_ascii_case("host"));
+        if !has_host {
+            head.push_str(&format!("Host: {authority}\r\n"));
+        }
+        for (key, value) in &self.headers {
+            head.push_str(&format!("{key}: {value}\r\n"));
+        }
+        let sends_body =
+            matches!(method, Method::Post | Method::Put | Method::Patch);
+        if sends_body || !body.is_empty() {
+            head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        head.push_str("Connection: close\r\n\r\n");
+
+        let mut request = head.into_bytes();
+        request.extend_from_slice(&body);
+        let mut stream = connect(authority, self.timeout)?;
+        exchange(&mut stream, &request, method == Method::Head)
+    }
+}
+
+/// The `host:port` and the path and query of an `http://` URL, the path
+/// being `/` if the URL has none
+pub(crate) fn split_url(url: &str) -> io::Result<(&str, String)> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "only http:// URLs")
+    })?;
+    let (authority, target) = match rest.find(['/', '?']) {
+        Some(at) => rest.split_at(at),
+        None => (rest, ""),
+    };
+    if authority.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "no host"));
+    }
+    if target.starts_with('/') {
+        Ok((authority, target.into()))
+    } else {
+        Ok((authority, format!("/{target}")))
+    }
+}
+
+/// `authority` with port 80 added unless it names one, looking past the
+/// brackets of an IPv6 address such as `[::1]` for it
+fn with_port(authority: &str) -> String {
+    let host_end = authority.rfind(']').map_or(0, |end| end + 1);
+    if authority[host_end..].contains(':') {
+        authority.to_string()
+    } else {
+        format!("{authority}:80")
+    }
+}
+
+/// Connect to `authority`, port 80 unless it names one, with `timeout` for
+/// connecting and then every read and write
+pub(crate) fn connect(
+    authority: &str,
+    timeout: Option<Duration>,
+) -> io::Result<TcpStream> {
+    let addr = with_port(authority);
+    let mut last_err = None;
+    for addr in addr.to_socket_addrs()? {
+        let stream = match timeout {
+            Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+            None => TcpStream::connect(addr),
+        };
+        match stream {
+            Ok(stream) => {
+                stream.set_read_timeout(timeout)?;
+                stream.set_write_timeout(timeout)?;
+                return Ok(stream);
+            }
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "no address for host")
+    }))
+}
+
+/// Send the serialised `request`, which must ask for the connection to be
+/// closed, and read the response until it is
+pub(crate) fn exchange(
+    stream: &mut TcpStream,
+    request: &[u8],
+    head_only: bool,
+) -> io::Result<Response> {
+    stream.write_all(request)?;
+    stream.flush()?;
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw)?;
+    Response::parse(&raw, head_only)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_urls() {
+        assert_eq!(
+            split_url("http://127.0.0.1:8080/users?page=2").unwrap(),
+            ("127.0.0.1:8080", "/users?page=2".into())
+        );
+        assert_eq!(
+            split_url("http://example.com").unwrap(),
+            ("example.com", "/".into())
+        );
+        assert_eq!(
+            split_url("http://example.com?q=1").unwrap(),
+            ("example.com", "/?q=1".into())
+        );
+        assert!(split_url("https://example.com").is_err());
+        assert!(split_url("http:///users").is_err());
+    }
+
+    #[test]
+    fn default_port() {
+        assert_eq!(with_port("example.com"), "example.com:80");
+        assert_eq!(with_port("example.com:8080"), "example.com:8080");
+        assert_eq!(with_port("[::1]"), "[::1]:80");
+        assert_eq!(with_port("[::1]:8080"), "[::1]:8080");
+        assert_eq!(
+            split_url("http://[::1]/users").unwrap(),
+            ("[::1]", "/users".into())
+        );
+    }
+}
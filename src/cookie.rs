@@ -0,0 +1,87 @@
+use std::fmt::Write;
+
+/// Whether browsers send a cookie with requests from other sites
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    /// Requires [`CookieAttributes::secure`] in current browsers
+    None,
+}
+
+impl From<SameSite> for &str {
+    fn from(value: SameSite) -> Self {
+        match value {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+/// The attributes of a cookie set with [`crate::Response::set_cookie`], none
+/// are set by default
+#[derive(Debug, Clone, Default)]
+pub struct CookieAttributes {
+    path: Option<String>,
+    max_age: Option<i64>,
+    http_only: bool,
+    secure: bool,
+    same_site: Option<SameSite>,
+}
+
+impl CookieAttributes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only send the cookie for requests under `path`
+    pub fn path(mut self, path: impl ToString) -> Self {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    /// How many seconds until the cookie expires, zero or less deletes it
+    pub fn max_age(mut self, seconds: i64) -> Self {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    /// Hide the cookie from JavaScript
+    pub fn http_only(mut self) -> Self {
+        self.http_only = true;
+        self
+    }
+
+    /// Only send the cookie over HTTPS
+    pub fn secure(mut self) -> Self {
+        self.secure = true;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// The value of a `Set-Cookie` header for the cookie `name`
+    pub(crate) fn set_cookie(&self, name: &str, value: &str) -> String {
+        let mut cookie = format!("{name}={value}");
+        if let Some(path) = &self.path {
+            let _ = write!(cookie, "; Path={path}");
+        }
+        if let Some(max_age) = self.max_age {
+            let _ = write!(cookie, "; Max-Age={max_age}");
+        }
+        if self.http_only {
+            cookie.push_str("; HttpOnly");
+        }
+        if self.secure {
+            cookie.push_str("; Secure");
+        }
+        if let Some(same_site) = self.same_site {
+            let _ = write!(cookie, "; SameSite={}", <&str>::from(same_site));
+        }
+        cookie
+    }
+}
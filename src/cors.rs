@@ -0,0 +1,249 @@
+use crate::{Method, Next, Request, Response, StatusCode};
+
+/// Which cross-origin requests are allowed, see [`crate::ServerBuilder::cors`]
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    /// `None` allows any origin
+    origins: Option<Vec<String>>,
+    methods: Vec<Method>,
+    headers: Vec<String>,
+    credentials: bool,
+    max_age: Option<u32>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CorsConfig {
+    /// Allows no origins until some are added, with the `GET`, `HEAD` and
+    /// `POST` methods
+    pub fn new() -> Self {
+        Self {
+            origins: Some(Vec::new()),
+            methods: vec![Method::Get, Method::Head, Method::Post],
+            headers: Vec::new(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+
+    /// Allow requests from `origin`, such as `https://example.com`
+    pub fn allow_origin(mut self, origin: impl ToString) -> Self {
+        if let Some(origins) = &mut self.origins {
+            origins.push(origin.to_string());
+        }
+        self
+    }
+
+    /// Allow requests from any origin
+    pub fn allow_any_origin(mut self) -> Self {
+        self.origins = None;
+        self
+    }
+
+    /// The methods preflight requests are told are allowed
+    pub fn allow_methods(mut self, methods: &[Method]) -> Self {
+        self.methods = methods.to_vec();
+        self
+    }
+
+    /// The request headers preflight requests are told are allowed, such as
+    /// `Content-Type` or `Authorization`
+    pub fn allow_headers(mut self, headers: &[&str]) -> Self {
+        self.headers =
+            headers.iter().map(|header| header.to_string()).collect();
+        self
+    }
+
+    /// Let browsers send cookies and credentials. The allowed origin is then
+    /// always sent back as given rather than as `*`, which browsers reject
+    /// with credentials.
+    pub fn allow_credentials(mut self, credentials: bool) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    /// How many seconds browsers can cache the result of a preflight request
+    pub fn max_age(mut self, seconds: u32) -> Self {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    /// Answer preflight requests and add the allowed origin to everything
+    /// else from an allowed origin
+    pub(crate) fn handle(&self, request: Request, next: Next) -> Response {
+        let origin = request
+            .header("origin")
+            .filter(|origin| self.allows(origin))
+            .map(str::to_string);
+        let preflight = *request.method() == Method::Options
+            && request.header("access-control-request-method").is_some();
+
+        if preflight {
+            let response = Response::new().status(StatusCode::NoContent);
+            let Some(origin) = origin else {
+                return response;
+            };
+            let methods: Vec<&str> =
+                self.methods.iter().map(|&method| method.into()).collect();
+            let mut response = self
+                .allow(response, origin)
+                .header("Access-Control-Allow-Methods", methods.join(", "));
+            if !self.headers.is_empty() {
+                response = response.header(
+                    "Access-Control-Allow-Headers",
+                    self.headers.join(", "),
+                );
+            }
+            if let Some(max_age) = self.max_age {
+                response = response.header("Access-Control-Max-Age", max_age);
+            }
+            return response;
+        }
+
+        let response = next.run(request);
+        match origin {
+            Some(origin) => self.allow(response, origin),
+            None => response,
+        }
+    }
+
+    fn allows(&self, origin: &str) -> bool {
+        self.origins.as_ref().is_none_or(|origins| {
+            origins.iter().any(|allowed| allowed == origin)
+        })
+    }
+
+    fn allow(&self, response: Response, origin: String) -> Response {
+        let response = if self.origins.is_none() && !self.credentials {
+            response.header("Access-Control-Allow-Origin", "*")
+        } else {
+            let mut response =
+                response.header("Access-Control-Allow-Origin", origin);
+            response.vary("Origin");
+            response
+        };
+        if self.credentials {
+            response.header("Access-Control-Allow-Credentials", "true")
+        } else {
+            response
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn users(_: Request) -> Response {
+        Response::new().body("users")
+    }
+
+    fn call(config: &CorsConfig, raw: &str) -> Response {
+        let request = Request::from_bytes(raw.as_bytes()).unwrap();
+        config.handle(request, Next::new(&[], &users))
+    }
+
+    #[test]
+    fn preflight() {
+        let config = CorsConfig::new()
+            .allow_origin("https://app.example.com")
+            .allow_methods(&[Method::Get, Method::Delete])
+            .allow_headers(&["Content-Type", "Authorization"])
+            .max_age(600);
+
+        let response = call(
+            &config,
+            "OPTIONS /users HTTP/1.1\r\nOrigin: https://app.example.com\r\nAccess-Control-Request-Method: DELETE\r\n\r\n",
+        );
+        assert_eq!(
+            response.header_value("access-control-allow-origin"),
+            Some("https://app.example.com")
+        );
+        assert_eq!(
+            response.header_value("access-control-allow-methods"),
+            Some("GET, DELETE")
+        );
+        assert_eq!(
+            response.header_value("access-control-allow-headers"),
+            Some("Content-Type, Authorization")
+        );
+        assert_eq!(
+            response.header_value("access-control-max-age"),
+            Some("600")
+        );
+        assert_eq!(
+            response.header_value("access-control-allow-credentials"),
+            None
+        );
+        assert!(String::from_utf8(response.serialise())
+            .unwrap()
+            .starts_with("HTTP/1.1 204 No Content\r\n"));
+    }
+
+    #[test]
+    fn disallowed_origin() {
+        let config = CorsConfig::new().allow_origin("https://app.example.com");
+
+        let response = call(
+            &config,
+            "OPTIONS /users HTTP/1.1\r\nOrigin: https://evil.example.com\r\nAccess-Control-Request-Method: GET\r\n\r\n",
+        );
+        assert_eq!(response.header_value("access-control-allow-origin"), None);
+        assert_eq!(response.header_value("access-control-allow-methods"), None);
+
+        let response = call(
+            &config,
+            "GET /users HTTP/1.1\r\nOrigin: https://evil.example.com\r\n\r\n",
+        );
+        assert_eq!(response.header_value("access-control-allow-origin"), None);
+        assert!(String::from_utf8(response.serialise())
+            .unwrap()
+            .ends_with("\r\n\r\nusers"));
+    }
+
+    #[test]
+    fn actual_request() {
+        let config = CorsConfig::new().allow_any_origin();
+        let response = call(
+            &config,
+            "GET /users HTTP/1.1\r\nOrigin: https://app.example.com\r\n\r\n",
+        );
+        assert_eq!(
+            response.header_value("access-control-allow-origin"),
+            Some("*")
+        );
+        assert!(String::from_utf8(response.serialise())
+            .unwrap()
+            .ends_with("\r\n\r\nusers"));
+
+        let config = config.allow_credentials(true);
+        let response = call(
+            &config,
+            "GET /users HTTP/1.1\r\nOrigin: https://app.example.com\r\n\r\n",
+        );
+        assert_eq!(
+            response.header_value("access-control-allow-origin"),
+            Some("https://app.example.com")
+        );
+        assert_eq!(
+            response.header_value("access-control-allow-credentials"),
+            Some("true")
+        );
+        assert_eq!(response.header_value("vary"), Some("Origin"));
+
+        let request = Request::from_bytes(
+            b"GET /users HTTP/1.1\r\nOrigin: https://app.example.com\r\n\r\n",
+        )
+        .unwrap();
+        let varied = |_| Response::new().header("Vary", "Accept-Language");
+        let response = config.handle(request, Next::new(&[], &varied));
+        assert_eq!(
+            response.header_value("vary"),
+            Some("Accept-Language, Origin")
+        );
+    }
+}
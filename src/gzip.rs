@@ -0,0 +1,69 @@
+use flate2::{write::GzEncoder, Compression};
+use std::io::Write;
+
+/// Bodies shorter than this are sent as they are, the gzip header and footer
+/// would eat most of the saving
+pub(crate) const MIN_LEN: usize = 1024;
+
+/// Whether a body of `content_type` is worth compressing. Most image, audio
+/// and video formats are compressed already, as are archives.
+pub(crate) fn compressible(content_type: Option<&str>) -> bool {
+    let Some(content_type) = content_type else {
+        return true;
+    };
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    if essence == "image/svg+xml" {
+        return true;
+    }
+    !(essence.starts_with("image/")
+        || essence.starts_with("audio/")
+        || essence.starts_with("video/")
+        || matches!(
+            essence.as_str(),
+            "application/gzip" | "application/x-gzip" | "application/zip"
+        ))
+}
+
+pub(crate) fn encode(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(data)
+        .expect("writing to a Vec can't fail");
+    encoder.finish().expect("writing to a Vec can't fail")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn encode_round_trip() {
+        let body = "hello world ".repeat(200);
+
+        let encoded = encode(body.as_bytes());
+        assert!(encoded.len() < body.len());
+
+        let mut decoded = String::new();
+        GzDecoder::new(encoded.as_slice())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, body);
+    }
+
+    #[test]
+    fn skip_compressed_types() {
+        assert!(compressible(None));
+        assert!(compressible(Some("text/html; charset=utf-8")));
+        assert!(compressible(Some("image/svg+xml")));
+        assert!(!compressible(Some("image/png")));
+        assert!(!compressible(Some("Video/MP4")));
+        assert!(!compressible(Some("application/gzip")));
+    }
+}
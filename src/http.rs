@@ -1,29 +1,269 @@
-use std::collections::HashMap;
+use crate::{
+    chunked::ChunkedDecoder, multipart, uri, CookieAttributes, Part, Uri,
+};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt, fs,
+    io::{self, Write},
+    net::{IpAddr, SocketAddr},
+    ops::Range,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-#[derive(Debug)]
+/// Why a request couldn't be parsed
+#[derive(Debug, PartialEq, Eq)]
 pub enum Error {
+    /// The request line names a method the server doesn't know
     InvalidMethod,
+    /// The request line names a protocol other than HTTP/1.1, 1.0 or 0.9
     InvalidProtocol,
+    /// The request does not follow the HTTP message syntax
+    Malformed,
+    /// The request ended before the end of the headers
+    Incomplete,
+    /// The request line or headers aren't valid UTF-8
+    InvalidUtf8,
+    /// The declared body is larger than the server accepts
+    PayloadTooLarge,
+    /// The headers are larger than the server accepts
+    HeadersTooLarge,
+    /// The path climbs above the root with `..` segments
+    InvalidPath,
 }
 
-#[derive(Debug)]
+impl Error {
+    /// The status to respond with when a request fails with this error
+    pub(crate) fn status_code(&self) -> StatusCode {
+        match self {
+            Self::PayloadTooLarge => StatusCode::PayloadTooLarge,
+            Self::HeadersTooLarge => StatusCode::RequestHeaderFieldsTooLarge,
+            Self::InvalidProtocol => StatusCode::HttpVersionNotSupported,
+            Self::InvalidMethod
+            | Self::Malformed
+            | Self::Incomplete
+            | Self::InvalidUtf8
+            | Self::InvalidPath => StatusCode::BadRequest,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::InvalidMethod => "unknown request method",
+            Self::InvalidProtocol => "unsupported HTTP version",
+            Self::Malformed => "malformed request",
+            Self::Incomplete => "request ended before the end of the headers",
+            Self::InvalidUtf8 => "request headers aren't valid UTF-8",
+            Self::PayloadTooLarge => "request body is too large",
+            Self::HeadersTooLarge => "request headers are too large",
+            Self::InvalidPath => "request path climbs above the root",
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Lets handlers parsing requests of their own use `?` to fail with the
+/// status the server would have answered with
+impl From<Error> for HttpError {
+    fn from(err: Error) -> Self {
+        Self::new(err.status_code(), err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum StatusCode {
-    Ok = 200,
-    NoContent = 204,
-    NotFound = 404,
+    Continue,
+    SwitchingProtocols,
+    Ok,
+    Created,
+    Accepted,
+    NoContent,
+    PartialContent,
+    MovedPermanently,
+    Found,
+    SeeOther,
+    NotModified,
+    TemporaryRedirect,
+    PermanentRedirect,
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    Conflict,
+    PayloadTooLarge,
+    RangeNotSatisfiable,
+    UnprocessableEntity,
+    TooManyRequests,
+    RequestHeaderFieldsTooLarge,
+    InternalServerError,
+    BadGateway,
+    ServiceUnavailable,
+    GatewayTimeout,
+    HttpVersionNotSupported,
+    /// Any other code from 100 to 599, such as one relayed from an upstream
+    /// server, sent without a reason phrase
+    Other(u16),
+}
+
+impl StatusCode {
+    const ALL: &'static [Self] = &[
+        Self::Continue,
+        Self::SwitchingProtocols,
+        Self::Ok,
+        Self::Created,
+        Self::Accepted,
+        Self::NoContent,
+        Self::PartialContent,
+        Self::MovedPermanently,
+        Self::Found,
+        Self::SeeOther,
+        Self::NotModified,
+        Self::TemporaryRedirect,
+        Self::PermanentRedirect,
+        Self::BadRequest,
+        Self::Unauthorized,
+        Self::Forbidden,
+        Self::NotFound,
+        Self::MethodNotAllowed,
+        Self::Conflict,
+        Self::PayloadTooLarge,
+        Self::RangeNotSatisfiable,
+        Self::UnprocessableEntity,
+        Self::TooManyRequests,
+        Self::RequestHeaderFieldsTooLarge,
+        Self::InternalServerError,
+        Self::BadGateway,
+        Self::ServiceUnavailable,
+        Self::GatewayTimeout,
+        Self::HttpVersionNotSupported,
+    ];
+
+    /// Look up the status code for its numeric value
+    pub fn from_u16(code: u16) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|status| status.as_u16() == code)
+    }
+
+    pub fn as_u16(&self) -> u16 {
+        match self {
+            Self::Continue => 100,
+            Self::SwitchingProtocols => 101,
+            Self::Ok => 200,
+            Self::Created => 201,
+            Self::Accepted => 202,
+            Self::NoContent => 204,
+            Self::PartialContent => 206,
+            Self::MovedPermanently => 301,
+            Self::Found => 302,
+            Self::SeeOther => 303,
+            Self::NotModified => 304,
+            Self::TemporaryRedirect => 307,
+            Self::PermanentRedirect => 308,
+            Self::BadRequest => 400,
+            Self::Unauthorized => 401,
+            Self::Forbidden => 403,
+            Self::NotFound => 404,
+            Self::MethodNotAllowed => 405,
+            Self::Conflict => 409,
+            Self::PayloadTooLarge => 413,
+            Self::RangeNotSatisfiable => 416,
+            Self::UnprocessableEntity => 422,
+            Self::TooManyRequests => 429,
+            Self::RequestHeaderFieldsTooLarge => 431,
+            Self::InternalServerError => 500,
+            Self::BadGateway => 502,
+            Self::ServiceUnavailable => 503,
+            Self::GatewayTimeout => 504,
+            Self::HttpVersionNotSupported => 505,
+            Self::Other(code) => *code,
+        }
+    }
+
+    /// The canonical reason phrase, e.g. `Not Found`
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::Continue => "Continue",
+            Self::SwitchingProtocols => "Switching Protocols",
+            Self::Ok => "OK",
+            Self::Created => "Created",
+            Self::Accepted => "Accepted",
+            Self::NoContent => "No Content",
+            Self::PartialContent => "Partial Content",
+            Self::MovedPermanently => "Moved Permanently",
+            Self::Found => "Found",
+            Self::SeeOther => "See Other",
+            Self::TemporaryRedirect => "Temporary Redirect",
+            Self::PermanentRedirect => "Permanent Redirect",
+            Self::NotModified => "Not Modified",
+            Self::BadRequest => "Bad Request",
+            Self::Unauthorized => "Unauthorized",
+            Self::Forbidden => "Forbidden",
+            Self::NotFound => "Not Found",
+            Self::MethodNotAllowed => "Method Not Allowed",
+            Self::Conflict => "Conflict",
+            Self::PayloadTooLarge => "Payload Too Large",
+            Self::RangeNotSatisfiable => "Range Not Satisfiable",
+            Self::UnprocessableEntity => "Unprocessable Entity",
+            Self::TooManyRequests => "Too Many Requests",
+            Self::RequestHeaderFieldsTooLarge => {
+                "Request Header Fields Too Large"
+            }
+            Self::InternalServerError => "Internal Server Error",
+            Self::BadGateway => "Bad Gateway",
+            Self::GatewayTimeout => "Gateway Timeout",
+            Self::ServiceUnavailable => "Service Unavailable",
+            Self::HttpVersionNotSupported => "HTTP Version Not Supported",
+            Self::Other(_) => "",
+        }
+    }
 }
 
 impl std::fmt::Display for StatusCode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Ok => write!(f, "200 Okay"),
-            Self::NoContent => write!(f, "204 No Content"),
-            Self::NotFound => write!(f, "404 Not Found"),
+        write!(f, "{} {}", self.as_u16(), self.reason())
+    }
+}
+
+/// An error returned by a handler, turned into a response with its status
+#[derive(Debug)]
+pub struct HttpError {
+    status_code: StatusCode,
+    message: String,
+}
+
+impl HttpError {
+    pub fn new(status_code: StatusCode, message: impl ToString) -> Self {
+        Self {
+            status_code,
+            message: message.to_string(),
         }
     }
+
+    pub fn status_code(&self) -> StatusCode {
+        self.status_code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.status_code, self.message)
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+impl std::error::Error for HttpError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Protocol {
     Http1_1,
     Http1_0,
@@ -53,11 +293,31 @@ impl TryFrom<&str> for Protocol {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Method {
     Connect,
+    Delete,
     Get,
+    Head,
+    Options,
+    Patch,
     Post,
+    Put,
+}
+
+impl From<Method> for &str {
+    fn from(value: Method) -> Self {
+        match value {
+            Method::Connect => "CONNECT",
+            Method::Delete => "DELETE",
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Options => "OPTIONS",
+            Method::Patch => "PATCH",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+        }
+    }
 }
 
 impl TryFrom<&str> for Method {
@@ -66,19 +326,77 @@ impl TryFrom<&str> for Method {
     fn try_from(value: &str) -> Result<Self, Self::Error> {
         match value.to_lowercase().as_str() {
             "connect" => Ok(Self::Connect),
+            "delete" => Ok(Self::Delete),
             "get" => Ok(Self::Get),
+            "head" => Ok(Self::Head),
+            "options" => Ok(Self::Options),
+            "patch" => Ok(Self::Patch),
             "post" => Ok(Self::Post),
+            "put" => Ok(Self::Put),
             _ => Err(Error::InvalidMethod),
         }
     }
 }
 
+/// The `Server` header sent unless the handler sets its own
+const SERVER: &str = concat!("wee-http/", env!("CARGO_PKG_VERSION"));
+
+/// The `Content-Type` of a body the handler didn't give a type
+const DEFAULT_CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+
+/// The body of a streaming [`Response`], produced one chunk at a time
+struct Chunks(Box<dyn Iterator<Item = Vec<u8>> + Send>);
+
+impl fmt::Debug for Chunks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Chunks")
+    }
+}
+
+/// Takes over the connection once an upgrade response has been written
+pub(crate) type UpgradeHandler =
+    Box<dyn FnOnce(&mut dyn crate::websocket::Stream) + Send>;
+
+struct Upgrade(UpgradeHandler);
+
+impl fmt::Debug for Upgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Upgrade")
+    }
+}
+
+/// Produces the trailer headers once a streamed body has been written
+struct Trailers(Box<dyn FnOnce() -> Vec<(String, String)> + Send>);
+
+impl fmt::Debug for Trailers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Trailers")
+    }
+}
+
 #[derive(Debug)]
 pub struct Response {
     protocol: Protocol,
     status_code: StatusCode,
-    headers: HashMap<String, String>,
-    body: Option<String>,
+    /// In the order they are written, names can repeat for headers such as
+    /// `Set-Cookie`
+    headers: Vec<(String, String)>,
+    body: Option<Vec<u8>>,
+    chunks: Option<Chunks>,
+    trailers: Option<Trailers>,
+    upgrade: Option<Upgrade>,
+    /// Whether to add `Server` and `Content-Type` headers the handler didn't
+    /// set
+    default_headers: bool,
+    /// Gzip the body when it is written, see [`Response::compress`]
+    #[cfg(feature = "gzip")]
+    gzip: bool,
+}
+
+impl Default for Response {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Response {
@@ -86,129 +404,1315 @@ impl Response {
         Self {
             protocol: Protocol::Http1_1,
             status_code: StatusCode::Ok,
-            headers: HashMap::new(),
+            headers: Vec::new(),
             body: None,
+            chunks: None,
+            trailers: None,
+            upgrade: None,
+            default_headers: true,
+            #[cfg(feature = "gzip")]
+            gzip: false,
         }
     }
 
-    pub fn set_status_code(mut self, status_code: StatusCode) -> Self {
+    /// A response with the contents of the file at `path` as its body, with
+    /// the `Content-Type` guessed from the extension or else the contents and
+    /// a weak `ETag` from the file's size and modification time. Errors such
+    /// as [`io::ErrorKind::NotFound`] are returned for the handler to choose
+    /// a response.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let body = fs::read(path)?;
+        let modified = fs::metadata(path)?
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |since| since.as_secs());
+        let etag = format!("W/\"{:x}-{modified:x}\"", body.len());
+        let content_type = crate::mime::from_path(path)
+            .unwrap_or_else(|| crate::mime::sniff(&body));
+        Ok(Self::new()
+            .header("ETag", etag)
+            .header("Accept-Ranges", "bytes")
+            .set_body_with_type(body, content_type))
+    }
+
+    /// Have the client download the body as a file called `filename` rather
+    /// than show it. Quotes and backslashes are escaped, and names that
+    /// aren't plain ASCII are also sent percent-encoded as `filename*` with a
+    /// fallback for older clients.
+    pub fn attachment(self, filename: &str) -> Self {
+        self.header("Content-Disposition", content_disposition(filename))
+    }
+
+    /// Tell browsers to only use HTTPS for the site for the next `max_age`
+    /// with a `Strict-Transport-Security` header. Browsers ignore it on
+    /// plaintext responses.
+    pub fn hsts(self, max_age: Duration) -> Self {
+        self.header(
+            "Strict-Transport-Security",
+            format!("max-age={}", max_age.as_secs()),
+        )
+    }
+
+    /// Redirect the client to `location` with a `301 Moved Permanently` if
+    /// `permanent`, otherwise a `302 Found`. Clients commonly follow these
+    /// with a `GET` whatever the original method was, dropping the body of a
+    /// `POST`, see [`Response::redirect_preserve_method`].
+    pub fn redirect(location: impl ToString, permanent: bool) -> Self {
+        let status_code = if permanent {
+            StatusCode::MovedPermanently
+        } else {
+            StatusCode::Found
+        };
+        Self::new()
+            .status(status_code)
+            .header("Location", location)
+            .body("")
+    }
+
+    /// Redirect the client to `location` with a `308 Permanent Redirect` if
+    /// `permanent`, otherwise a `307 Temporary Redirect`. Unlike
+    /// [`Response::redirect`] the client must repeat the request with the same
+    /// method and body, so a `POST` stays a `POST`.
+    pub fn redirect_preserve_method(
+        location: impl ToString,
+        permanent: bool,
+    ) -> Self {
+        let status_code = if permanent {
+            StatusCode::PermanentRedirect
+        } else {
+            StatusCode::TemporaryRedirect
+        };
+        Self::new()
+            .status(status_code)
+            .header("Location", location)
+            .body("")
+    }
+
+    /// Redirect the client to `location` with a `303 See Other`, telling it
+    /// to follow up with a `GET`, such as after a form has been posted
+    pub fn see_other(location: impl ToString) -> Self {
+        Self::new()
+            .status(StatusCode::SeeOther)
+            .header("Location", location)
+            .body("")
+    }
+
+    /// A `200 OK` with `body`
+    pub fn ok(body: impl Into<Vec<u8>>) -> Self {
+        Self::new().body(body)
+    }
+
+    /// A `200 OK` with `body` as `text/plain; charset=utf-8`
+    pub fn text(body: impl ToString) -> Self {
+        Self::new().set_body_with_type(body.to_string(), DEFAULT_CONTENT_TYPE)
+    }
+
+    /// A `200 OK` with `body` as `text/html; charset=utf-8`
+    pub fn html(body: impl ToString) -> Self {
+        Self::new()
+            .set_body_with_type(body.to_string(), "text/html; charset=utf-8")
+    }
+
+    /// A `201 Created` pointing the client at the new resource at `location`
+    pub fn created(location: impl ToString) -> Self {
+        Self::new()
+            .status(StatusCode::Created)
+            .header("Location", location)
+            .body("")
+    }
+
+    /// A `204 No Content`, sent without a body or `Content-Length` as the
+    /// status means there is nothing to follow the headers
+    pub fn no_content() -> Self {
+        Self::new().status(StatusCode::NoContent)
+    }
+
+    /// A `400 Bad Request` with `message` as a text body explaining what the
+    /// client got wrong
+    pub fn bad_request(message: impl ToString) -> Self {
+        Self::new()
+            .status(StatusCode::BadRequest)
+            .text_body(message)
+    }
+
+    /// A `500 Internal Server Error`, with just the status as the body so no
+    /// details of the failure reach the client
+    pub fn internal_error() -> Self {
+        Self::new()
+            .status(StatusCode::InternalServerError)
+            .text_body(StatusCode::InternalServerError)
+    }
+
+    /// A response whose body is sent with `Transfer-Encoding: chunked`, for
+    /// when the length isn't known up front. Each item is written and flushed
+    /// to the client as soon as the iterator yields it, empty items are
+    /// skipped as a zero length chunk would end the body.
+    pub fn stream(
+        chunks: impl Iterator<Item = Vec<u8>> + Send + 'static,
+    ) -> Self {
+        let mut response = Self::new();
+        response.chunks = Some(Chunks(Box::new(chunks)));
+        response
+    }
+
+    /// Send headers after the last chunk of a [`Response::stream`], for values
+    /// only known once the body is done such as a checksum. The `names` are
+    /// declared up front in a `Trailer` header and `trailers` is called for
+    /// the values after the stream ends. Clients on HTTP/1.0 have no chunked
+    /// encoding to carry them, so they get neither.
+    pub fn trailers(
+        self,
+        names: &[&str],
+        trailers: impl FnOnce() -> Vec<(String, String)> + Send + 'static,
+    ) -> Self {
+        let mut response = self.header("Trailer", names.join(", "));
+        response.trailers = Some(Trailers(Box::new(trailers)));
+        response
+    }
+
+    /// A `text/event-stream` of server-sent events, each formatted with
+    /// [`crate::sse::event`] and sent as soon as the iterator yields it. The
+    /// response lasts as long as the iterator, which can block between events.
+    pub fn event_stream(
+        events: impl Iterator<Item = String> + Send + 'static,
+    ) -> Self {
+        Self::stream(events.map(String::into_bytes))
+            .header("Content-Type", "text/event-stream")
+            .header("Cache-Control", "no-cache")
+    }
+
+    pub fn status(mut self, status_code: StatusCode) -> Self {
         self.status_code = status_code;
         self
     }
 
-    pub fn add_header(
+    /// Set the header `key`, replacing any existing value regardless of case
+    pub fn header(mut self, key: impl ToString, value: impl ToString) -> Self {
+        self.insert_header(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Set the body, text or raw bytes such as an image
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = Some(body.into());
+        self
+    }
+
+    /// Set the body to `body` written out as text, such as a number
+    pub fn text_body(self, body: impl ToString) -> Self {
+        self.body(body.to_string())
+    }
+
+    /// The protocol written in the status line, `HTTP/1.1` unless set
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Add a header without replacing any already set with the same name
+    pub fn append_header(
         mut self,
         key: impl ToString,
         value: impl ToString,
     ) -> Self {
-        self.headers.insert(key.to_string(), value.to_string());
+        self.headers.push((key.to_string(), value.to_string()));
         self
     }
 
-    pub fn set_body(mut self, body: impl ToString) -> Self {
-        self.body = Some(body.to_string());
-        self
+    /// Add a `Set-Cookie` header for the cookie `name`, each call adds another
+    /// cookie
+    pub fn set_cookie(
+        self,
+        name: &str,
+        value: &str,
+        attributes: CookieAttributes,
+    ) -> Self {
+        self.append_header("Set-Cookie", attributes.set_cookie(name, value))
     }
 
-    pub fn serialise(&mut self) -> String {
-        let protocol: &str = self.protocol.into();
-        let status_code = &self.status_code;
+    /// The same as [`Response::status`]
+    pub fn set_status_code(self, status_code: StatusCode) -> Self {
+        self.status(status_code)
+    }
+
+    /// The same as [`Response::header`]
+    pub fn add_header(self, key: impl ToString, value: impl ToString) -> Self {
+        self.header(key, value)
+    }
+
+    /// The same as [`Response::body`]
+    pub fn set_body(self, body: impl Into<Vec<u8>>) -> Self {
+        self.body(body)
+    }
+
+    /// The status the response is sent with, `200 OK` unless set
+    pub fn status_code(&self) -> &StatusCode {
+        &self.status_code
+    }
+
+    /// The value of the header `name`, ignoring case. For headers added with
+    /// [`Response::append_header`] this is the first value.
+    pub fn header_value(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The body as it was set, before any compression, empty for streamed
+    /// responses and those without one
+    pub fn body_bytes(&self) -> &[u8] {
+        self.body.as_deref().unwrap_or_default()
+    }
+
+    /// A response with `value` serialised as its JSON body, or a
+    /// `500 Internal Server Error` if it can't be serialised
+    #[cfg(feature = "serde")]
+    pub fn json<T: serde::Serialize>(value: &T) -> Self {
+        match serde_json::to_string(value) {
+            Ok(body) => {
+                Self::new().set_body_with_type(body, "application/json")
+            }
+            Err(err) => Self::new()
+                .status(StatusCode::InternalServerError)
+                .text_body(err),
+        }
+    }
+
+    /// Set the body along with its `Content-Type`, see [`crate::mime`] for
+    /// working out the type
+    pub fn set_body_with_type(
+        self,
+        body: impl Into<Vec<u8>>,
+        content_type: impl ToString,
+    ) -> Self {
+        self.header("Content-Type", content_type).body(body)
+    }
+
+    /// The response as it is written to the client, with the default headers
+    /// and `Content-Length` added. The response itself is left as it is, so
+    /// serialising it again gives the same bytes.
+    pub fn serialise(&self) -> Vec<u8> {
+        let body = self.encoded_body();
+        let mut serialised = self.head(body.as_deref()).into_bytes();
+        serialised.extend_from_slice(body.as_deref().unwrap_or_default());
+        serialised
+    }
+
+    /// Parse a response from the whole of `buf`, as read from a server that
+    /// closed the connection after it. The body is what `Content-Length` or
+    /// the chunked encoding declares, or everything after the headers with
+    /// neither. Those framing headers aren't kept since serialising the
+    /// response adds them back.
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, Error> {
+        Self::parse(buf, false)
+    }
+
+    /// [`Response::from_bytes`] for the answer to a request such as `HEAD`
+    /// when `head_only` is set, with no body to follow so the server's
+    /// `Content-Length` is kept as it is
+    pub(crate) fn parse(buf: &[u8], head_only: bool) -> Result<Self, Error> {
+        let header_len = buf
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .ok_or(Error::Incomplete)?;
+        let head = std::str::from_utf8(&buf[..header_len])
+            .map_err(|_| Error::InvalidUtf8)?;
+        let mut lines = head.split("\r\n");
+
+        let status_line = lines.next().ok_or(Error::Malformed)?;
+        let mut status_line = status_line.splitn(3, ' ');
+        let protocol =
+            status_line.next().ok_or(Error::Malformed)?.try_into()?;
+        let status_code = status_line
+            .next()
+            .filter(|code| {
+                code.len() == 3 && code.bytes().all(|b| b.is_ascii_digit())
+            })
+            .and_then(|code| code.parse().ok())
+            .filter(|code| (100..600).contains(code))
+            .map(|code| {
+                StatusCode::from_u16(code).unwrap_or(StatusCode::Other(code))
+            })
+            .ok_or(Error::Malformed)?;
+
+        let mut response = Self::new().protocol(protocol).status(status_code);
+        let mut content_len = None;
+        let mut chunked = false;
+        for line in lines {
+            let (key, value) = line.split_once(':').ok_or(Error::Malformed)?;
+            let (key, value) = (key.trim(), value.trim());
+            if key.eq_ignore_ascii_case("content-length") {
+                content_len =
+                    Some(parse_length(value).ok_or(Error::Malformed)?);
+            } else if key.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.to_ascii_lowercase().contains("chunked");
+            } else {
+                response = response.append_header(key, value);
+            }
+        }
+
+        let no_body = head_only
+            || matches!(
+                status_code,
+                StatusCode::NoContent | StatusCode::NotModified
+            );
+        if no_body {
+            if let Some(content_len) = content_len {
+                response = response.header("Content-Length", content_len);
+            }
+            return Ok(response);
+        }
+
+        let rest = &buf[header_len + 4..];
+        let body = if chunked {
+            let mut decoder = ChunkedDecoder::new(usize::MAX);
+            if !decoder.decode(rest)? {
+                return Err(Error::Incomplete);
+            }
+            decoder.into_body()
+        } else if let Some(content_len) = content_len {
+            rest.get(..content_len).ok_or(Error::Incomplete)?.to_vec()
+        } else {
+            rest.to_vec()
+        };
+        Ok(response.body(body))
+    }
+
+    /// Write the response to `stream`, streaming the body chunk by chunk if
+    /// it was created with [`Response::stream`]. Without `include_body`, as
+    /// for a `HEAD` request, the headers are the same but the body is left
+    /// off. Returns how many bytes of body were written.
+    pub(crate) fn write_to(
+        &mut self,
+        stream: &mut impl Write,
+        include_body: bool,
+    ) -> io::Result<usize> {
+        let Some(Chunks(chunks)) = self.chunks.take() else {
+            let body = self.encoded_body();
+            stream.write_all(self.head(body.as_deref()).as_bytes())?;
+            if !include_body {
+                return Ok(0);
+            }
+            let body = body.as_deref().unwrap_or_default();
+            stream.write_all(body)?;
+            return Ok(body.len());
+        };
+
+        self.headers
+            .retain(|(key, _)| !key.eq_ignore_ascii_case("Content-Length"));
+        // HTTP/1.0 has no chunked encoding, the body ends when the
+        // connection closes
+        let chunked = self.protocol == Protocol::Http1_1;
+        if chunked {
+            self.insert_header("Transfer-Encoding".into(), "chunked".into());
+        } else {
+            self.headers
+                .retain(|(key, _)| !key.eq_ignore_ascii_case("Trailer"));
+        }
+        stream.write_all(self.head(None).as_bytes())?;
+        stream.flush()?;
+        if !include_body {
+            return Ok(0);
+        }
+
+        let mut written = 0;
+        for chunk in chunks.filter(|chunk| !chunk.is_empty()) {
+            if chunked {
+                stream
+                    .write_all(format!("{:x}\r\n", chunk.len()).as_bytes())?;
+            }
+            stream.write_all(&chunk)?;
+            if chunked {
+                stream.write_all(b"\r\n")?;
+            }
+            stream.flush()?;
+            written += chunk.len();
+        }
+        if chunked {
+            stream.write_all(b"0\r\n")?;
+            if let Some(Trailers(trailers)) = self.trailers.take() {
+                for (key, value) in trailers() {
+                    stream
+                        .write_all(format!("{key}: {value}\r\n").as_bytes())?;
+                }
+            }
+            stream.write_all(b"\r\n")?;
+        }
+        stream.flush()?;
+        Ok(written)
+    }
 
-        if let Some(body) = &self.body {
+    /// Turn a `200 OK` into a `304 Not Modified` without a body if its `ETag`
+    /// is one of the tags in `if_none_match`, the value of an `If-None-Match`
+    /// header. Tags are compared weakly, ignoring any `W/` prefix.
+    pub(crate) fn not_modified(&mut self, if_none_match: &str) {
+        if self.status_code != StatusCode::Ok {
+            return;
+        }
+        let Some(etag) = self.header_value("ETag") else {
+            return;
+        };
+        let opaque =
+            |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+        let etag = opaque(etag);
+        let matches = if_none_match.trim() == "*"
+            || if_none_match.split(',').any(|tag| opaque(tag) == etag);
+        if matches {
+            self.status_code = StatusCode::NotModified;
+            self.body = None;
+            self.chunks = None;
             self.headers
-                .insert("Content-Length".into(), body.len().to_string());
+                .retain(|(key, _)| !key.eq_ignore_ascii_case("Content-Length"));
+        }
+    }
+
+    /// Answer the `Range` header `range` with a `206 Partial Content` holding
+    /// the bytes of a `200 OK` body it asks for, or `416 Range Not
+    /// Satisfiable` if they are all past the end. Only responses sent with
+    /// `Accept-Ranges: bytes` are split, and only when `if_range` is absent
+    /// or names this version of the body by a strong `ETag` or its
+    /// `Last-Modified` date. Headers asking for several ranges or that can't
+    /// be parsed are ignored and the whole body sent.
+    pub(crate) fn range(&mut self, range: &str, if_range: Option<&str>) {
+        let accepts_ranges = self
+            .header_value("Accept-Ranges")
+            .is_some_and(|value| value.trim().eq_ignore_ascii_case("bytes"));
+        if self.status_code != StatusCode::Ok || !accepts_ranges {
+            return;
+        }
+        if let Some(if_range) = if_range.map(str::trim) {
+            // Weak tags never match, they don't promise the same bytes
+            let current = if if_range.starts_with('"') {
+                self.header_value("ETag")
+            } else {
+                self.header_value("Last-Modified")
+            };
+            if current.map(str::trim) != Some(if_range) {
+                return;
+            }
+        }
+        let Some(body) = &self.body else {
+            return;
+        };
+        let len = body.len();
+        match byte_range(range, len) {
+            None => {}
+            Some(Some(range)) => {
+                let content_range =
+                    format!("bytes {}-{}/{len}", range.start, range.end - 1);
+                self.body = Some(body[range].to_vec());
+                self.status_code = StatusCode::PartialContent;
+                self.insert_header("Content-Range".into(), content_range);
+            }
+            Some(None) => {
+                self.body = Some(Vec::new());
+                self.status_code = StatusCode::RangeNotSatisfiable;
+                self.insert_header(
+                    "Content-Range".into(),
+                    format!("bytes */{len}"),
+                );
+            }
+        }
+    }
+
+    /// Hand the connection to `handler` after the response is written, see
+    /// [`crate::websocket::upgrade`]
+    pub(crate) fn on_upgrade(
+        mut self,
+        handler: impl FnOnce(&mut dyn crate::websocket::Stream) + Send + 'static,
+    ) -> Self {
+        self.upgrade = Some(Upgrade(Box::new(handler)));
+        self
+    }
+
+    pub(crate) fn take_upgrade(&mut self) -> Option<UpgradeHandler> {
+        self.upgrade.take().map(|Upgrade(handler)| handler)
+    }
+
+    /// Whether the body is streamed with [`Response::stream`]
+    pub(crate) fn streamed(&self) -> bool {
+        self.chunks.is_some()
+    }
+
+    /// Gzip the body when it is written if it is long enough to be worth it
+    /// and isn't compressed already
+    #[cfg(feature = "gzip")]
+    pub(crate) fn compress(&mut self) {
+        let worthwhile = self.status_code != StatusCode::PartialContent
+            && self.chunks.is_none()
+            && self.header_value("Content-Encoding").is_none()
+            && self
+                .body
+                .as_ref()
+                .is_some_and(|body| body.len() >= crate::gzip::MIN_LEN)
+            && crate::gzip::compressible(self.header_value("Content-Type"));
+        if worthwhile {
+            self.gzip = true;
+            self.vary("Accept-Encoding");
+        }
+    }
+
+    /// The body as it is sent, gzipped if [`Response::compress`] found that
+    /// worthwhile
+    fn encoded_body(&self) -> Option<Cow<'_, [u8]>> {
+        let body = self.body.as_deref()?;
+        #[cfg(feature = "gzip")]
+        if self.gzip {
+            return Some(Cow::Owned(crate::gzip::encode(body)));
+        }
+        Some(Cow::Borrowed(body))
+    }
+
+    /// Stop [`Response::serialise`] adding the `Server` and `Content-Type`
+    /// headers the handler didn't set, see
+    /// [`crate::ServerBuilder::default_headers`]
+    pub(crate) fn skip_default_headers(&mut self) {
+        self.default_headers = false;
+    }
+
+    /// The headers every response has that the handler didn't set, a `Date`,
+    /// and unless skipped a `Server` and a plain text `Content-Type` for a
+    /// body
+    fn missing_default_headers(&self) -> Vec<(String, String)> {
+        let mut missing = Vec::new();
+        if self.header_value("Date").is_none() {
+            let date = crate::date::http_date(SystemTime::now());
+            missing.push(("Date".into(), date));
+        }
+        if !self.default_headers {
+            return missing;
+        }
+        if self.header_value("Server").is_none() {
+            missing.push(("Server".into(), SERVER.into()));
+        }
+        let has_body = self.chunks.is_some()
+            || self.body.as_ref().is_some_and(|body| !body.is_empty());
+        if has_body && self.header_value("Content-Type").is_none() {
+            missing.push(("Content-Type".into(), DEFAULT_CONTENT_TYPE.into()));
         }
+        missing
+    }
+
+    /// The status line and headers sent with `body`, the one place the
+    /// `Content-Length` is worked out so it is always the length of the bytes
+    /// written, after any encoding. Up to and including the blank line.
+    fn head(&self, body: Option<&[u8]>) -> String {
+        let mut headers = self.headers.clone();
+        headers.extend(self.missing_default_headers());
+        if let Some(body) = body {
+            #[cfg(feature = "gzip")]
+            if self.gzip {
+                set_header(&mut headers, "Content-Encoding", "gzip".into());
+            }
+            set_header(&mut headers, "Content-Length", body.len().to_string());
+        }
+
+        let protocol: &str = self.protocol.into();
+        let mut head = format!("{protocol} {}\r\n", self.status_code);
+        for (key, value) in headers {
+            head.push_str(&format!("{key}: {value}\r\n"));
+        }
+        head.push_str("\r\n");
+        head
+    }
 
-        let body = self.body.take().unwrap_or("".into());
+    /// Header names are case-insensitive, so replace any existing header
+    /// regardless of how it was cased
+    fn insert_header(&mut self, key: String, value: String) {
+        set_header(&mut self.headers, &key, value);
+    }
+
+    /// Add `name` to the headers listed in `Vary`, keeping those already
+    /// there
+    pub(crate) fn vary(&mut self, name: &str) {
+        let mut names = self
+            .header_values("Vary")
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|existing| !existing.is_empty())
+            .collect::<Vec<_>>();
+        if names.iter().any(|existing| {
+            *existing == "*" || existing.eq_ignore_ascii_case(name)
+        }) {
+            return;
+        }
+        names.push(name);
+        let value = names.join(", ");
+        self.insert_header("Vary".into(), value);
+    }
 
-        let mut headers = String::new();
+    /// Every value of the header `name`, ignoring case
+    pub(crate) fn header_values<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> impl Iterator<Item = &'a str> {
         self.headers
             .iter()
-            .for_each(|(k, v)| headers.push_str(&format!("{k}: {v}\r\n")));
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
 
-        format!("{protocol} {status_code}\r\n{headers}\r\n{body}",)
+    /// Drop the headers `keep` returns false for, given the lowercased name
+    pub(crate) fn retain_headers(
+        &mut self,
+        mut keep: impl FnMut(&str) -> bool,
+    ) {
+        self.headers
+            .retain(|(key, _)| keep(&key.to_ascii_lowercase()));
     }
 }
 
+/// Replace any header `key` in `headers`, ignoring case
+fn set_header(headers: &mut Vec<(String, String)>, key: &str, value: String) {
+    headers.retain(|(existing, _)| !existing.eq_ignore_ascii_case(key));
+    headers.push((key.into(), value));
+}
+
 #[derive(Debug)]
 pub struct Request {
     protocol: Protocol,
     method: Method,
-    path: String,
-    headers: HashMap<String, String>,
-    body: String,
-    query: Option<HashMap<String, String>>,
+    uri: Uri,
+    headers: HashMap<String, Vec<String>>,
+    body: Vec<u8>,
+    query: HashMap<String, String>,
+    params: HashMap<String, String>,
+    route: Option<String>,
+    peer_addr: Option<SocketAddr>,
+    client_ip: Option<IpAddr>,
+    /// Whether the request came over TLS, or the proxy in front of the server
+    /// says it did
+    secure: bool,
+    /// Identifies the request in logs and to the client, empty unless the
+    /// server assigns IDs
+    id: String,
+    /// The first line exactly as it was sent, such as `GET /a?b=c HTTP/1.1`
+    request_line: String,
 }
 
 impl Request {
     pub fn protocol(&self) -> &Protocol {
         &self.protocol
     }
+    /// The first line of the request exactly as it was sent, such as
+    /// `GET /search?q=wee HTTP/1.1`
+    pub fn request_line(&self) -> &str {
+        &self.request_line
+    }
+    /// The method as it was sent, before it was parsed into [`Method`]
+    pub fn method_str(&self) -> &str {
+        self.request_line.split(' ').next().unwrap_or_default()
+    }
+    /// The target as it was sent, with any query string and before the path
+    /// was decoded or normalised, such as `/files/../a%20b?q=1`
+    pub fn request_target(&self) -> &str {
+        self.request_line.split(' ').nth(1).unwrap_or_default()
+    }
     pub fn method(&self) -> &Method {
         &self.method
     }
+    /// The decoded path, normalised so duplicate slashes, `.` and `..`
+    /// segments and any trailing slash are gone, such as `/users/7`. The root
+    /// is always `/`.
     pub fn path(&self) -> &str {
-        &self.path
+        self.uri.path()
+    }
+    /// The path and query the request was sent to, with the query's pairs
+    /// in order
+    pub fn uri(&self) -> &Uri {
+        &self.uri
     }
-    pub fn query(&self) -> &Option<HashMap<String, String>> {
+    /// The decoded value of the query parameter `key`
+    pub fn query(&self, key: &str) -> Option<&str> {
+        self.query.get(key).map(String::as_str)
+    }
+    pub fn query_params(&self) -> &HashMap<String, String> {
         &self.query
     }
-    pub fn body(&self) -> &String {
+    /// The value captured by the `:name` segment of the matched route
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+    pub(crate) fn set_method(&mut self, method: Method) {
+        self.method = method;
+    }
+    pub(crate) fn set_params(&mut self, params: HashMap<String, String>) {
+        self.params = params;
+    }
+    /// The registered path of the route the request matched, such as
+    /// `/users/:id` for `/users/7`
+    pub fn route(&self) -> Option<&str> {
+        self.route.as_deref()
+    }
+    pub(crate) fn set_route(&mut self, route: &str) {
+        self.route = Some(route.into());
+    }
+    /// The address of the other end of the connection, `None` for a request
+    /// that wasn't read from a socket
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+    /// The address of the client, the peer's unless the server trusts
+    /// `X-Forwarded-For`, see [`crate::ServerBuilder::trust_forwarded_for`]
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.client_ip
+            .or_else(|| self.peer_addr.map(|addr| addr.ip()))
+    }
+    /// Record where the request came from, with `trust_forwarded_for` taking
+    /// the client from the last `X-Forwarded-For` address, the one added by
+    /// the proxy in front of the server
+    pub(crate) fn set_peer_addr(
+        &mut self,
+        peer_addr: Option<SocketAddr>,
+        trust_forwarded_for: bool,
+    ) {
+        self.peer_addr = peer_addr;
+        if trust_forwarded_for {
+            self.client_ip = self
+                .header_all("x-forwarded-for")
+                .last()
+                .and_then(|value| value.rsplit(',').next())
+                .and_then(|addr| addr.trim().parse().ok());
+        }
+    }
+    /// Whether the request came over TLS, or with `trust_forwarded_for` the
+    /// scheme the last `X-Forwarded-Proto` says the client used, see
+    /// [`crate::ServerBuilder::trust_forwarded_for`]
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+    /// The ID the server gave the request and sends back in `X-Request-Id`,
+    /// empty unless [`crate::ServerBuilder::request_ids`] is on
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+    pub(crate) fn set_id(&mut self, id: String) {
+        self.id = id;
+    }
+    /// Record whether the connection is TLS, with `trust_forwarded_for`
+    /// taking the scheme from the last `X-Forwarded-Proto` if there is one
+    pub(crate) fn set_secure(
+        &mut self,
+        secure: bool,
+        trust_forwarded_for: bool,
+    ) {
+        let forwarded = trust_forwarded_for
+            .then(|| self.header_all("x-forwarded-proto").last().copied())
+            .flatten()
+            .and_then(|value| value.rsplit(',').next());
+        self.secure = match forwarded {
+            Some(proto) => proto.trim().eq_ignore_ascii_case("https"),
+            None => secure,
+        };
+    }
+    /// The body as text, `None` if it isn't valid UTF-8 such as an image or
+    /// other binary upload, see [`Request::body_bytes`]
+    pub fn body(&self) -> Option<&str> {
+        std::str::from_utf8(&self.body).ok()
+    }
+    /// The body exactly as it was sent
+    pub fn body_bytes(&self) -> &[u8] {
         &self.body
     }
-    pub fn body_mut(&mut self) -> &mut String {
+    pub fn body_mut(&mut self) -> &mut Vec<u8> {
         &mut self.body
     }
-    pub fn headers(&self) -> &HashMap<String, String> {
+    /// The headers sent with the request, keyed by their lowercased name with
+    /// every value in the order they were sent
+    pub fn headers(&self) -> &HashMap<String, Vec<String>> {
         &self.headers
     }
-    pub fn content_len(&self) -> usize {
+    /// The first value of the header `name`, ignoring case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.header_all(name).first().copied()
+    }
+    /// Every value sent for the header `name`, ignoring case
+    pub fn header_all(&self, name: &str) -> Vec<&str> {
         self.headers
-            .get("content-length")
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(0)
+            .get(&name.to_lowercase())
+            .map(|values| values.iter().map(String::as_str).collect())
+            .unwrap_or_default()
     }
-    pub fn from_bytes(buf: &[u8]) -> Self {
-        let raw_str = std::str::from_utf8(buf).unwrap();
-        let (raw_headers, body) = raw_str.split_once("\r\n\r\n").unwrap();
-        let mut raw_headers = raw_headers.lines();
-
-        let mut first_line = raw_headers.next().unwrap().split(' ');
-        let method = first_line.next().unwrap().try_into().unwrap();
-        let mut uri = first_line.next().unwrap().splitn(2, '?');
-        let path = uri.next().unwrap().trim_end_matches('/').to_string();
-        let query = match uri.next() {
-            Some(query) => {
-                let mut queries = HashMap::new();
-                let query_parts = query.split("&");
-                for part in query_parts {
-                    let (key, value) = part.split_once("=").unwrap();
-                    queries.insert(key.into(), value.into());
-                }
-                Some(queries)
-            }
-            None => None,
-        };
-
-        let protocol = first_line.next().unwrap().try_into().unwrap();
-
-        let mut headers = HashMap::new();
-        raw_headers.for_each(|header| {
-            let (key, value) = header.split_once(':').unwrap();
-            headers.insert(key.trim().to_lowercase(), value.trim().into());
-        });
-
-        let body = body.to_string();
+    /// The media type of the body from the `Content-Type` header, without any
+    /// parameters such as `charset`
+    pub fn content_type(&self) -> Option<&str> {
+        self.header("content-type")?
+            .split(';')
+            .next()
+            .map(str::trim)
+            .filter(|essence| !essence.is_empty())
+    }
+    /// Whether the body is JSON, `application/json` or a `+json` type such as
+    /// `application/problem+json`
+    pub fn is_json(&self) -> bool {
+        self.content_type().is_some_and(|essence| {
+            let essence = essence.to_ascii_lowercase();
+            essence == "application/json" || essence.ends_with("+json")
+        })
+    }
+    /// Whether the body is `application/x-www-form-urlencoded`
+    pub fn is_form(&self) -> bool {
+        self.content_type().is_some_and(|essence| {
+            essence.eq_ignore_ascii_case("application/x-www-form-urlencoded")
+        })
+    }
+    /// The parts of a `multipart/form-data` body, such as a form uploading a
+    /// file. Fails with [`Error::Malformed`] if the request has another
+    /// content type or the body doesn't follow its boundary.
+    pub fn multipart(&self) -> Result<Vec<Part>, Error> {
+        let (essence, params) = self
+            .header("content-type")
+            .and_then(|value| value.split_once(';'))
+            .ok_or(Error::Malformed)?;
+        if !essence.trim().eq_ignore_ascii_case("multipart/form-data") {
+            return Err(Error::Malformed);
+        }
+        let boundary = multipart::param(params, "boundary")
+            .filter(|boundary| !boundary.is_empty())
+            .ok_or(Error::Malformed)?;
+        multipart::parse(&self.body, &boundary)
+    }
+    /// Deserialise the body as JSON, failing with `400 Bad Request`
+    #[cfg(feature = "serde")]
+    pub fn json<T: serde::de::DeserializeOwned>(&self) -> Result<T, HttpError> {
+        serde_json::from_slice(&self.body)
+            .map_err(|err| HttpError::new(StatusCode::BadRequest, err))
+    }
+    /// The fields of an `application/x-www-form-urlencoded` body, empty if the
+    /// request has another content type or the body can't be decoded
+    pub fn form(&self) -> HashMap<String, String> {
+        if !self.is_form() {
+            return HashMap::new();
+        }
+        self.body()
+            .and_then(|body| parse_urlencoded(body.trim_end()).ok())
+            .unwrap_or_default()
+    }
+    /// Whether the client wants to keep the connection open after this
+    /// request, the default for HTTP/1.1 but not for older protocols. A
+    /// `Connection: close` always ends it, whatever the protocol.
+    pub fn keep_alive(&self) -> bool {
+        let has_token = |token: &str| {
+            self.header_all("connection").iter().any(|value| {
+                value
+                    .split(',')
+                    .any(|part| part.trim().eq_ignore_ascii_case(token))
+            })
+        };
+        if has_token("close") {
+            return false;
+        }
+        match self.protocol {
+            Protocol::Http1_1 => true,
+            Protocol::Http1_0 | Protocol::Http0_9 => has_token("keep-alive"),
+        }
+    }
+    /// Whether `Accept-Encoding` allows a response body encoded with `coding`,
+    /// such as `gzip`. A coding named explicitly takes precedence over `*`,
+    /// and either can be refused with `;q=0`.
+    pub fn accepts_encoding(&self, coding: &str) -> bool {
+        let mut wildcard = false;
+        for part in self
+            .header_all("accept-encoding")
+            .iter()
+            .flat_map(|value| value.split(','))
+        {
+            let mut params = part.split(';');
+            let name = params.next().unwrap_or_default().trim();
+            let accepted = !params.any(|param| {
+                param
+                    .trim()
+                    .strip_prefix("q=")
+                    .is_some_and(|q| q.parse::<f32>().is_ok_and(|q| q == 0.0))
+            });
+            if name.eq_ignore_ascii_case(coding) {
+                return accepted;
+            }
+            if name == "*" {
+                wildcard = accepted;
+            }
+        }
+        wildcard
+    }
+    /// Whether `Accept` allows a response of the media type `mime`, such as
+    /// `application/json`. Every type is allowed without the header.
+    pub fn accepts(&self, mime: &str) -> bool {
+        self.accept_quality(mime) > 0.0
+    }
+    /// The type from `offered` the client's `Accept` ranks highest, the
+    /// earliest on a tie and `None` if it refuses all of them. The most
+    /// specific range matching a type gives its quality, so `text/html`
+    /// beats `text/*` which beats `*/*`.
+    pub fn preferred<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+        let mut best = None;
+        for &mime in offered {
+            let quality = self.accept_quality(mime);
+            if quality > best.map_or(0.0, |(_, best)| best) {
+                best = Some((mime, quality));
+            }
+        }
+        best.map(|(mime, _)| mime)
+    }
+    /// The quality `Accept` gives `mime` from the most specific range
+    /// matching it, ignoring any parameters such as `charset`
+    fn accept_quality(&self, mime: &str) -> f32 {
+        let values = self.header_all("accept");
+        if values.is_empty() {
+            return 1.0;
+        }
+        let mime = mime.split(';').next().unwrap_or_default().trim();
+        let (kind, subtype) = mime.split_once('/').unwrap_or((mime, ""));
 
-        Self {
+        // The specificity and quality of the best match so far
+        let mut best: Option<(u8, f32)> = None;
+        for part in values.iter().flat_map(|value| value.split(',')) {
+            let mut params = part.split(';');
+            let range = params.next().unwrap_or_default().trim();
+            let Some((range_kind, range_subtype)) = range.split_once('/')
+            else {
+                continue;
+            };
+            let specificity = match (range_kind, range_subtype) {
+                ("*", "*") => 1,
+                (range_kind, "*") if range_kind.eq_ignore_ascii_case(kind) => 2,
+                (range_kind, range_subtype)
+                    if range_kind.eq_ignore_ascii_case(kind)
+                        && range_subtype.eq_ignore_ascii_case(subtype) =>
+                {
+                    3
+                }
+                _ => continue,
+            };
+            let quality = params
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(1.0, |q| q.trim().parse::<f32>().unwrap_or(0.0))
+                .clamp(0.0, 1.0);
+            if best.is_none_or(|(best, _)| specificity > best) {
+                best = Some((specificity, quality));
+            }
+        }
+        best.map_or(0.0, |(_, quality)| quality)
+    }
+    /// The token from an `Authorization: Bearer <token>` header, matching the
+    /// scheme case-insensitively
+    pub fn bearer_token(&self) -> Option<&str> {
+        let (scheme, token) = self
+            .header("authorization")?
+            .trim()
+            .split_once(|c: char| c.is_ascii_whitespace())?;
+        let token = token.trim();
+        (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty())
+            .then_some(token)
+    }
+    /// The value of the cookie `name` from the `Cookie` header
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.header_all("cookie")
+            .iter()
+            .flat_map(|value| value.split(';'))
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(key, _)| key.trim() == name)
+            .map(|(_, value)| {
+                let value = value.trim();
+                value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .unwrap_or(value)
+            })
+    }
+    /// Whether the body is sent with `Transfer-Encoding: chunked`
+    pub fn chunked(&self) -> bool {
+        self.header_all("transfer-encoding").iter().any(|value| {
+            value
+                .split(',')
+                .any(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+        })
+    }
+    /// The length of the body declared by `Content-Length`, `None` without
+    /// the header. Requests with a length that isn't a number fail to parse
+    /// with [`Error::Malformed`], so any present here is valid.
+    pub fn content_length(&self) -> Option<usize> {
+        self.header("content-length")
+            .and_then(|len| parse_length(len.split(',').next()?))
+    }
+    /// The declared length of the body, 0 without a `Content-Length`
+    pub fn content_len(&self) -> usize {
+        self.content_length().unwrap_or(0)
+    }
+    /// Parse the request at the start of `buf`, returning it along with the
+    /// number of bytes it took up. Unlike [`Request::from_bytes`] the body is
+    /// only what `Content-Length` or the chunked encoding declares, so
+    /// anything after it can be kept for the next request. Fails with
+    /// [`Error::Incomplete`] if `buf` ends first.
+    pub fn parse(buf: &[u8]) -> Result<(Self, usize), Error> {
+        let header_len = buf
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .ok_or(Error::Incomplete)?
+            + 4;
+        let mut request = Self::from_bytes(&buf[..header_len])?;
+
+        let rest = &buf[header_len..];
+        let (body, body_len) = if request.chunked() {
+            let mut decoder = ChunkedDecoder::new(usize::MAX);
+            if !decoder.decode(rest)? {
+                return Err(Error::Incomplete);
+            }
+            let consumed = decoder.consumed();
+            (decoder.into_body(), consumed)
+        } else {
+            let len = request.content_len();
+            (rest.get(..len).ok_or(Error::Incomplete)?.to_vec(), len)
+        };
+        request.body = body;
+
+        Ok((request, header_len + body_len))
+    }
+    /// Parse a request from the whole of `buf`, everything after the headers
+    /// is the body. Only the headers need to be text, the body is kept as
+    /// the raw bytes.
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, Error> {
+        let (raw_headers, body) =
+            match buf.windows(4).position(|window| window == b"\r\n\r\n") {
+                Some(end) => (&buf[..end], Some(&buf[end + 4..])),
+                None => (buf, None),
+            };
+        let raw_headers =
+            std::str::from_utf8(raw_headers).map_err(|_| Error::InvalidUtf8)?;
+        let body = body.ok_or(Error::Incomplete)?.to_vec();
+        let mut raw_headers = raw_headers.lines();
+
+        let request_line = raw_headers.next().ok_or(Error::Malformed)?;
+        let mut first_line = request_line.split(' ');
+        let method = first_line.next().ok_or(Error::Malformed)?.try_into()?;
+        let uri = uri::parse(first_line.next().ok_or(Error::Malformed)?)?;
+        let query = uri.query_pairs().iter().cloned().collect();
+
+        let protocol = first_line.next().ok_or(Error::Malformed)?.try_into()?;
+        if first_line.next().is_some() {
+            return Err(Error::Malformed);
+        }
+
+        let mut headers = HashMap::new();
+        for header in raw_headers {
+            let (key, value) =
+                header.split_once(':').ok_or(Error::Malformed)?;
+            headers
+                .entry(key.trim().to_lowercase())
+                .or_insert_with(Vec::new)
+                .push(value.trim().into());
+        }
+        // Only chunked, applied once and last, says where a coded body ends
+        if let Some(values) = headers.get("transfer-encoding") {
+            let codings = values
+                .iter()
+                .flat_map(|value: &String| value.split(','))
+                .map(str::trim)
+                .filter(|coding| !coding.is_empty())
+                .collect::<Vec<_>>();
+            let chunked =
+                |coding: &&str| coding.eq_ignore_ascii_case("chunked");
+            if codings.iter().filter(|&coding| chunked(coding)).count() != 1
+                || !codings.last().is_some_and(chunked)
+            {
+                return Err(Error::Malformed);
+            }
+        }
+        // Lengths the body can't be read by, several that disagree, or one
+        // alongside a transfer coding, would leave the server and any proxy
+        // in front of it split on where the next request starts
+        if let Some(lengths) = headers.get("content-length") {
+            if headers.contains_key("transfer-encoding") {
+                return Err(Error::Malformed);
+            }
+            let mut lengths = lengths
+                .iter()
+                .flat_map(|value: &String| value.split(','))
+                .map(parse_length);
+            let first = lengths.next().flatten().ok_or(Error::Malformed)?;
+            if lengths.any(|len| len != Some(first)) {
+                return Err(Error::Malformed);
+            }
+        }
+
+        Ok(Self {
             headers,
             body,
             protocol,
             method,
-            path,
+            uri,
             query,
+            params: HashMap::new(),
+            route: None,
+            peer_addr: None,
+            client_ip: None,
+            secure: false,
+            id: String::new(),
+            request_line: request_line.into(),
+        })
+    }
+}
+
+/// A `Content-Length` value, which is only ever digits so signs and other
+/// forms `str::parse` would take are refused
+fn parse_length(len: &str) -> Option<usize> {
+    let len = len.trim();
+    if len.is_empty() || !len.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    len.parse().ok()
+}
+
+/// The `Content-Disposition` value for downloading `filename`, see RFC 6266
+fn content_disposition(filename: &str) -> String {
+    let mut quoted = String::with_capacity(filename.len());
+    for char in filename.chars() {
+        match char {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(char);
+            }
+            ' '..='~' => quoted.push(char),
+            _ => quoted.push('_'),
+        }
+    }
+    if filename.bytes().all(|byte| (b' '..=b'~').contains(&byte)) {
+        return format!("attachment; filename=\"{quoted}\"");
+    }
+
+    let mut encoded = String::with_capacity(filename.len() * 3);
+    for byte in filename.bytes() {
+        match byte {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'!'
+            | b'#'
+            | b'$'
+            | b'&'
+            | b'+'
+            | b'-'
+            | b'.'
+            | b'^'
+            | b'_'
+            | b'`'
+            | b'|'
+            | b'~' => encoded.push(byte as char),
+            byte => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    format!("attachment; filename=\"{quoted}\"; filename*=UTF-8''{encoded}")
+}
+
+/// The part of a `len` byte body a `Range` header asks for, `None` if the
+/// header should be ignored and `Some(None)` if the range is past the end
+fn byte_range(range: &str, len: usize) -> Option<Option<Range<usize>>> {
+    let (unit, spec) = range.trim().split_once('=')?;
+    if !unit.eq_ignore_ascii_case("bytes") || spec.contains(',') {
+        return None;
+    }
+    let (first, last) = spec.split_once('-')?;
+    let (first, last) = (first.trim(), last.trim());
+
+    // `bytes=-500` is the last 500 bytes
+    if first.is_empty() {
+        let suffix: usize = last.parse().ok()?;
+        if suffix == 0 || len == 0 {
+            return Some(None);
         }
+        return Some(Some(len.saturating_sub(suffix)..len));
     }
+
+    let first: usize = first.parse().ok()?;
+    let last = match last {
+        "" => usize::MAX,
+        last => last.parse().ok().filter(|&last| last >= first)?,
+    };
+    if first >= len {
+        return Some(None);
+    }
+    Some(Some(first..last.min(len - 1) + 1))
+}
+
+/// `path` in the one form routes are matched in, with empty and `.` segments
+/// dropped and each `..` removing the segment before it. There is no trailing
+/// slash except on the root, which is just `/`. `None` if a `..` climbs above
+/// the root.
+pub(crate) fn normalise_path(path: &str) -> Option<String> {
+    // The `OPTIONS *` request asks about the server as a whole
+    if path == "*" {
+        return Some(path.into());
+    }
+    let mut segments = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            segment => segments.push(segment),
+        }
+    }
+    if segments.is_empty() {
+        return Some("/".into());
+    }
+    Some(
+        segments
+            .iter()
+            .map(|segment| format!("/{segment}"))
+            .collect(),
+    )
+}
+
+/// Parse `key=value&other=value` pairs as they appear in query strings and
+/// form bodies. Later duplicate keys replace earlier ones and a key without
+/// `=` has an empty value.
+fn parse_urlencoded(raw: &str) -> Result<HashMap<String, String>, Error> {
+    Ok(urlencoded_pairs(raw)?.into_iter().collect())
+}
+
+/// The decoded pairs of `raw` in order, keeping repeated names
+pub(crate) fn urlencoded_pairs(
+    raw: &str,
+) -> Result<Vec<(String, String)>, Error> {
+    raw.split('&')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let (key, value) = part.split_once('=').unwrap_or((part, ""));
+            Ok((percent_decode(key, true)?, percent_decode(value, true)?))
+        })
+        .collect()
+}
+
+/// Decode `%XX` escapes in `raw`, and `+` as a space when `plus_as_space` is
+/// set as it is for query strings and forms
+pub(crate) fn percent_decode(
+    raw: &str,
+    plus_as_space: bool,
+) -> Result<String, Error> {
+    let mut decoded = Vec::with_capacity(raw.len());
+    let mut bytes = raw.bytes();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'%' => {
+                let hex = [
+                    bytes.next().ok_or(Error::Malformed)?,
+                    bytes.next().ok_or(Error::Malformed)?,
+                ];
+                let hex =
+                    std::str::from_utf8(&hex).map_err(|_| Error::Malformed)?;
+                decoded.push(
+                    u8::from_str_radix(hex, 16)
+                        .map_err(|_| Error::Malformed)?,
+                );
+            }
+            b'+' if plus_as_space => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| Error::InvalidUtf8)
 }
 
 #[cfg(test)]
@@ -219,12 +1723,1018 @@ mod tests {
     fn respond_to_ping() {
         let request = "POST / HTTP/1.1\r\nHost: 6095-143-159-233-243.ngrok-free.app\r\nUser-Agent: Discord-Interactions/1.0 (+https://discord.com)\r\nContent-Length: 577\r\nContent-Type: application/json\r\nX-Forwarded-Proto: https\r\nX-Signature-Ed25519: 9a10c00a02d8b5d56bf17f3059790c9603a0bba41d8e\r\nAccept-Encoding: gzip\r\n\r\n{\"app_permissions\":\"180224\",\"application_id\":\"1216441490306502796\",\"entitlements\":[],\"id\":\"1218320751015235605\",\"token\":\"foo\",\"type\":1,\"user\":{\"avatar\":\"c6a249645d462\",\"avatar_decoration_data\":null,\"bot\":true,\"discriminator\":\"0000\",\"global_name\":\"Discord\",\"id\":\"6439452\",\"public_flags\":1,\"system\":true,\"username\":\"discord\"},\"version\":1}";
 
-        let http = Request::from_bytes(request.as_bytes());
+        let _http = Request::from_bytes(request.as_bytes()).unwrap();
     }
 
     #[test]
     fn no_body() {
         let request = "POST / HTTP/1.1\r\n\r\n";
-        let http = Request::from_bytes(request.as_bytes());
+        let _http = Request::from_bytes(request.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn status_code_round_trip() {
+        for code in [200, 201, 304, 404, 429, 503] {
+            assert_eq!(StatusCode::from_u16(code).unwrap().as_u16(), code);
+        }
+        assert_eq!(StatusCode::from_u16(418), None);
+    }
+
+    #[test]
+    fn status_code_display() {
+        assert_eq!(StatusCode::Ok.to_string(), "200 OK");
+        assert_eq!(StatusCode::NotFound.to_string(), "404 Not Found");
+        assert_eq!(
+            StatusCode::UnprocessableEntity.to_string(),
+            "422 Unprocessable Entity"
+        );
+        assert_eq!(StatusCode::Other(418).as_u16(), 418);
+        assert_eq!(StatusCode::Other(418).to_string(), "418 ");
+    }
+
+    #[test]
+    fn parse_methods() {
+        let methods = [
+            ("CONNECT", Method::Connect),
+            ("DELETE", Method::Delete),
+            ("GET", Method::Get),
+            ("HEAD", Method::Head),
+            ("OPTIONS", Method::Options),
+            ("PATCH", Method::Patch),
+            ("POST", Method::Post),
+            ("PUT", Method::Put),
+        ];
+        for (raw, method) in methods {
+            let request = format!("{raw} / HTTP/1.1\r\n\r\n");
+            let http = Request::from_bytes(request.as_bytes()).unwrap();
+            assert_eq!(*http.method(), method);
+
+            let round_trip: &str = method.into();
+            assert_eq!(round_trip, raw);
+        }
+    }
+
+    #[test]
+    fn parse_methods_case_insensitive() {
+        let http =
+            Request::from_bytes("pAtCh / HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+        assert_eq!(*http.method(), Method::Patch);
+    }
+
+    #[test]
+    fn parse_truncated() {
+        let request = "GET / HTTP/1.1\r\nHost: localhost\r\n";
+        let err = Request::from_bytes(request.as_bytes()).unwrap_err();
+        assert_eq!(err, Error::Incomplete);
+    }
+
+    #[test]
+    fn keep_alive_tokens() {
+        let keep_alive = |raw: &str| {
+            Request::from_bytes(raw.as_bytes()).unwrap().keep_alive()
+        };
+        assert!(keep_alive("GET / HTTP/1.1\r\n\r\n"));
+        assert!(!keep_alive("GET / HTTP/1.1\r\nConnection: Close\r\n\r\n"));
+        assert!(!keep_alive("GET / HTTP/1.0\r\n\r\n"));
+        assert!(keep_alive(
+            "GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n"
+        ));
+        assert!(!keep_alive(
+            "GET / HTTP/1.0\r\nConnection: keep-alive, close\r\n\r\n"
+        ));
+        assert!(!keep_alive(
+            "GET / HTTP/1.1\r\nConnection: upgrade\r\nConnection: close\r\n\r\n"
+        ));
+    }
+
+    #[test]
+    fn display_error() {
+        assert_eq!(Error::InvalidMethod.to_string(), "unknown request method");
+        assert_eq!(
+            Error::InvalidPath.to_string(),
+            "request path climbs above the root"
+        );
+
+        let err = HttpError::from(Error::HeadersTooLarge);
+        assert_eq!(
+            err.to_string(),
+            "431 Request Header Fields Too Large: request headers are too large"
+        );
+
+        let boxed: Box<dyn std::error::Error> = Error::Malformed.into();
+        assert_eq!(boxed.to_string(), "malformed request");
+    }
+
+    #[test]
+    fn parse_multipart() {
+        let request = Request::from_bytes(
+            b"POST /upload HTTP/1.1\r\n\
+            Content-Type: multipart/form-data; boundary=\"----abc\"\r\n\
+            \r\n\
+            ------abc\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\
+            \r\n\
+            holiday\r\n\
+            ------abc\r\n\
+            Content-Disposition: form-data; name=\"photo\"; filename=\"beach.png\"\r\n\
+            Content-Type: image/png\r\n\
+            \r\n\
+            \x89PNG\x00\xff\r\n\
+            ------abc--\r\n",
+        )
+        .unwrap();
+
+        let parts = request.multipart().unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name(), "title");
+        assert_eq!(parts[0].text(), Some("holiday"));
+        assert_eq!(parts[1].filename(), Some("beach.png"));
+        assert_eq!(parts[1].content_type(), "image/png");
+        assert_eq!(parts[1].data(), b"\x89PNG\x00\xff");
+
+        let request =
+            Request::from_bytes(b"POST / HTTP/1.1\r\n\r\nhello").unwrap();
+        assert_eq!(request.multipart(), Err(Error::Malformed));
+    }
+
+    #[test]
+    fn raw_request_line() {
+        let request = Request::from_bytes(
+            b"get /files/../a%20b?q=1 HTTP/1.0\r\nHost: localhost\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(request.request_line(), "get /files/../a%20b?q=1 HTTP/1.0");
+        assert_eq!(request.method_str(), "get");
+        assert_eq!(*request.method(), Method::Get);
+        assert_eq!(request.request_target(), "/files/../a%20b?q=1");
+        assert_eq!(request.path(), "/a b");
+        assert_eq!(*request.protocol(), Protocol::Http1_0);
+    }
+
+    #[test]
+    fn parse_binary_body() {
+        let request =
+            Request::from_bytes(b"POST / HTTP/1.1\r\n\r\n\xff\x00\xfe")
+                .unwrap();
+        assert_eq!(request.body_bytes(), b"\xff\x00\xfe");
+        assert_eq!(request.body(), None);
+    }
+
+    #[test]
+    fn parse_garbage() {
+        let err = Request::from_bytes(&[0xff, 0xfe, 0x00, 0x01]).unwrap_err();
+        assert_eq!(err, Error::InvalidUtf8);
+
+        let err = Request::from_bytes(b"garbage\r\n\r\n").unwrap_err();
+        assert_eq!(err, Error::InvalidMethod);
+
+        let err = Request::from_bytes(b"GET\r\n\r\n").unwrap_err();
+        assert_eq!(err, Error::Malformed);
+
+        let err =
+            Request::from_bytes(b"GET / HTTP/1.1 extra\r\n\r\n").unwrap_err();
+        assert_eq!(err, Error::Malformed);
+    }
+
+    #[test]
+    fn parse_header_without_colon() {
+        let request = "GET / HTTP/1.1\r\nHost localhost\r\n\r\n";
+        let err = Request::from_bytes(request.as_bytes()).unwrap_err();
+        assert_eq!(err, Error::Malformed);
+    }
+
+    #[test]
+    fn parse_query() {
+        let request = "GET /search?q=rust%20lang&page=2 HTTP/1.1\r\n\r\n";
+        let http = Request::from_bytes(request.as_bytes()).unwrap();
+        assert_eq!(http.path(), "/search");
+        assert_eq!(http.query("q"), Some("rust lang"));
+        assert_eq!(http.query("page"), Some("2"));
+        assert_eq!(http.query("missing"), None);
+        assert_eq!(http.query_params().len(), 2);
+    }
+
+    #[test]
+    fn parse_query_form_style() {
+        let request = "GET /search?q=rust+lang&flag HTTP/1.1\r\n\r\n";
+        let http = Request::from_bytes(request.as_bytes()).unwrap();
+        assert_eq!(http.query("q"), Some("rust lang"));
+        assert_eq!(http.query("flag"), Some(""));
+    }
+
+    #[test]
+    fn parse_query_bad_escape() {
+        let request = "GET /search?q=%2 HTTP/1.1\r\n\r\n";
+        let err = Request::from_bytes(request.as_bytes()).unwrap_err();
+        assert_eq!(err, Error::Malformed);
+    }
+
+    #[test]
+    fn parse_encoded_path() {
+        let request = "GET /hello%20world HTTP/1.1\r\n\r\n";
+        let http = Request::from_bytes(request.as_bytes()).unwrap();
+        assert_eq!(http.path(), "/hello world");
+
+        let request = "GET /a%2Fb HTTP/1.1\r\n\r\n";
+        let http = Request::from_bytes(request.as_bytes()).unwrap();
+        assert_eq!(http.path(), "/a/b");
+
+        let request = "GET /caf%C3%A9 HTTP/1.1\r\n\r\n";
+        let http = Request::from_bytes(request.as_bytes()).unwrap();
+        assert_eq!(http.path(), "/café");
+
+        let request = "GET /a+b HTTP/1.1\r\n\r\n";
+        let http = Request::from_bytes(request.as_bytes()).unwrap();
+        assert_eq!(http.path(), "/a+b");
+    }
+
+    #[test]
+    fn normalise_request_path() {
+        for (raw, expected) in [
+            ("/", "/"),
+            ("", "/"),
+            ("/a/..", "/"),
+            ("//a//b", "/a/b"),
+            ("/a/../b", "/b"),
+            ("/a/./b/", "/a/b"),
+            ("/a/b/..", "/a"),
+            ("/a/%2E%2E/b", "/b"),
+        ] {
+            let request = format!("GET {raw} HTTP/1.1\r\n\r\n");
+            let http = Request::from_bytes(request.as_bytes()).unwrap();
+            assert_eq!(http.path(), expected, "{raw}");
+        }
+
+        for raw in ["/..", "/a/../../b", "/%2E%2E/etc/passwd"] {
+            let request = format!("GET {raw} HTTP/1.1\r\n\r\n");
+            let err = Request::from_bytes(request.as_bytes()).unwrap_err();
+            assert_eq!(err, Error::InvalidPath, "{raw}");
+        }
+    }
+
+    #[test]
+    fn parse_bad_path_escape() {
+        let request = "GET /%G1 HTTP/1.1\r\n\r\n";
+        let err = Request::from_bytes(request.as_bytes()).unwrap_err();
+        assert_eq!(err, Error::Malformed);
+
+        let request = "GET /100% HTTP/1.1\r\n\r\n";
+        let err = Request::from_bytes(request.as_bytes()).unwrap_err();
+        assert_eq!(err, Error::Malformed);
+    }
+
+    #[test]
+    fn header_case_insensitive() {
+        let request = "GET / HTTP/1.1\r\nhost: localhost\r\n\r\n";
+        let http = Request::from_bytes(request.as_bytes()).unwrap();
+        assert_eq!(http.header("Host"), Some("localhost"));
+        assert_eq!(http.header("HOST"), Some("localhost"));
+    }
+
+    #[test]
+    fn response_header_replaces_differently_cased() {
+        let response = Response::new()
+            .header("content-type", "text/plain")
+            .header("Content-Type", "text/html")
+            .header("content-length", 100)
+            .body("hi");
+        assert_eq!(response.header_value("CONTENT-TYPE"), Some("text/html"));
+
+        let serialised = String::from_utf8(response.serialise()).unwrap();
+        assert_eq!(serialised.matches("ontent-Type").count(), 1);
+        assert!(serialised.contains("\r\nContent-Type: text/html\r\n"));
+        assert!(serialised.contains("\r\nContent-Length: 2\r\n"));
+        assert!(!serialised.contains("content-length"));
+    }
+
+    #[test]
+    fn read_custom_header() {
+        let request = "GET / HTTP/1.1\r\nAuthorization: Bearer abc\r\nX-Custom:  value \r\n\r\n";
+        let http = Request::from_bytes(request.as_bytes()).unwrap();
+        assert_eq!(http.header("authorization"), Some("Bearer abc"));
+        assert_eq!(http.header("X-Custom"), Some("value"));
+        assert_eq!(http.header("X-Missing"), None);
+        assert_eq!(http.headers()["x-custom"], ["value"]);
+    }
+
+    #[test]
+    fn repeated_headers() {
+        let request = "GET / HTTP/1.1\r\nAccept: text/html\r\naccept: application/json\r\n\r\n";
+        let http = Request::from_bytes(request.as_bytes()).unwrap();
+        assert_eq!(http.header("Accept"), Some("text/html"));
+        assert_eq!(
+            http.header_all("Accept"),
+            ["text/html", "application/json"]
+        );
+        assert!(http.header_all("X-Missing").is_empty());
+    }
+
+    #[test]
+    fn body_with_type() {
+        let response = Response::new().set_body_with_type(
+            "body { margin: 0 }",
+            crate::mime::from_path("site.css").unwrap(),
+        );
+        assert_eq!(
+            response.header_value("content-type"),
+            Some("text/css; charset=utf-8")
+        );
+
+        let body = "<html></html>";
+        let response = Response::new()
+            .set_body_with_type(body, crate::mime::sniff(body.as_bytes()));
+        assert_eq!(
+            response.header_value("content-type"),
+            Some("text/html; charset=utf-8")
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct User {
+        name: String,
+        age: u8,
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_round_trip() {
+        let user = User {
+            name: "Nessie".into(),
+            age: 255,
+        };
+        let response = Response::json(&user);
+        assert_eq!(
+            response.header_value("Content-Type"),
+            Some("application/json")
+        );
+
+        let serialised = String::from_utf8(response.serialise()).unwrap();
+        let body = serialised.split_once("\r\n\r\n").unwrap().1;
+        let request = format!(
+            "POST / HTTP/1.1\r\nContent-Type: application/json\r\n\r\n{body}"
+        );
+        let request = Request::from_bytes(request.as_bytes()).unwrap();
+        assert_eq!(request.json::<User>().unwrap(), user);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_invalid() {
+        let request = "POST / HTTP/1.1\r\n\r\n{\"name\": 1}";
+        let request = Request::from_bytes(request.as_bytes()).unwrap();
+        let err = request.json::<User>().unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BadRequest);
+    }
+
+    #[test]
+    fn parse_form() {
+        let request = "POST / HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\n\r\nname=Loch+Ness%21&age=30&age=31&empty=&flag";
+        let http = Request::from_bytes(request.as_bytes()).unwrap();
+        let form = http.form();
+        assert_eq!(form["name"], "Loch Ness!");
+        assert_eq!(form["age"], "31");
+        assert_eq!(form["empty"], "");
+        assert_eq!(form["flag"], "");
+        assert_eq!(form.len(), 4);
+    }
+
+    #[test]
+    fn parse_form_wrong_content_type() {
+        let request =
+            "POST / HTTP/1.1\r\nContent-Type: text/plain\r\n\r\nname=value";
+        let http = Request::from_bytes(request.as_bytes()).unwrap();
+        assert!(http.form().is_empty());
+    }
+
+    #[test]
+    fn parse_consumed() {
+        let buf = b"POST /users HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET / HTTP/1.1\r\n\r\n";
+        let (request, consumed) = Request::parse(buf).unwrap();
+        assert_eq!(request.body(), Some("hello"));
+        assert_eq!(consumed, buf.len() - "GET / HTTP/1.1\r\n\r\n".len());
+
+        let (request, consumed) = Request::parse(&buf[consumed..]).unwrap();
+        assert_eq!(*request.method(), Method::Get);
+        assert_eq!(consumed, "GET / HTTP/1.1\r\n\r\n".len());
+
+        let buf = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\nrest";
+        let (request, consumed) = Request::parse(buf).unwrap();
+        assert_eq!(request.body(), Some("hello"));
+        assert_eq!(consumed, buf.len() - 4);
+
+        assert_eq!(
+            Request::parse(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhel")
+                .unwrap_err(),
+            Error::Incomplete
+        );
+        assert_eq!(
+            Request::parse(b"GET / HTTP/1.1\r\n").unwrap_err(),
+            Error::Incomplete
+        );
+    }
+
+    #[test]
+    fn parse_byte_range() {
+        assert_eq!(byte_range("bytes=0-9", 100), Some(Some(0..10)));
+        assert_eq!(byte_range("Bytes=90-", 100), Some(Some(90..100)));
+        assert_eq!(byte_range("bytes=90-200", 100), Some(Some(90..100)));
+        assert_eq!(byte_range("bytes=-10", 100), Some(Some(90..100)));
+        assert_eq!(byte_range("bytes=-200", 100), Some(Some(0..100)));
+        assert_eq!(byte_range("bytes=100-", 100), Some(None));
+        assert_eq!(byte_range("bytes=-0", 100), Some(None));
+        assert_eq!(byte_range("bytes=9-0", 100), None);
+        assert_eq!(byte_range("bytes=0-1,5-6", 100), None);
+        assert_eq!(byte_range("items=0-9", 100), None);
+        assert_eq!(byte_range("bytes=a-b", 100), None);
+    }
+
+    #[test]
+    fn content_type() {
+        let request = "POST / HTTP/1.1\r\nContent-Type: application/json; charset=utf-8\r\n\r\n";
+        let http = Request::from_bytes(request.as_bytes()).unwrap();
+        assert_eq!(http.content_type(), Some("application/json"));
+        assert!(http.is_json());
+        assert!(!http.is_form());
+
+        let request = "POST / HTTP/1.1\r\nContent-Type: Application/X-WWW-Form-Urlencoded\r\n\r\n";
+        let http = Request::from_bytes(request.as_bytes()).unwrap();
+        assert!(http.is_form());
+        assert!(!http.is_json());
+
+        let request =
+            "POST / HTTP/1.1\r\nContent-Type: application/problem+json\r\n\r\n";
+        let http = Request::from_bytes(request.as_bytes()).unwrap();
+        assert!(http.is_json());
+
+        let http = Request::from_bytes(b"POST / HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(http.content_type(), None);
+        assert!(!http.is_json());
+    }
+
+    #[test]
+    fn write_streamed_response() {
+        let chunks = ["hello", "", " world"].map(|chunk| chunk.into());
+        let mut response = Response::stream(chunks.into_iter());
+
+        let mut wire = Vec::new();
+        response.write_to(&mut wire, true).unwrap();
+        let wire = String::from_utf8(wire).unwrap();
+
+        assert!(wire.contains("\r\nTransfer-Encoding: chunked\r\n"));
+        assert!(!wire.contains("Content-Length"));
+        assert!(
+            wire.ends_with("\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n")
+        );
+    }
+
+    #[test]
+    fn write_trailers() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        let sum = Arc::new(AtomicUsize::new(0));
+        let counted = sum.clone();
+        let chunks = ["hello", " world"].into_iter().map(move |chunk| {
+            counted.fetch_add(chunk.len(), Ordering::SeqCst);
+            chunk.into()
+        });
+        let mut response =
+            Response::stream(chunks).trailers(&["X-Length"], move || {
+                let len = sum.load(Ordering::SeqCst);
+                vec![("X-Length".into(), len.to_string())]
+            });
+
+        let mut wire = Vec::new();
+        response.write_to(&mut wire, true).unwrap();
+        let wire = String::from_utf8(wire).unwrap();
+        assert!(wire.contains("\r\nTrailer: X-Length\r\n"));
+        assert!(wire.ends_with("\r\n6\r\n world\r\n0\r\nX-Length: 11\r\n\r\n"));
+
+        let mut wire = Vec::new();
+        Response::stream(["hello".into()].into_iter())
+            .trailers(&["X-Length"], || vec![("X-Length".into(), "5".into())])
+            .protocol(Protocol::Http1_0)
+            .write_to(&mut wire, true)
+            .unwrap();
+        let wire = String::from_utf8(wire).unwrap();
+        assert!(!wire.contains("Trailer"));
+        assert!(wire.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn accept_encoding() {
+        let request = Request::from_bytes(
+            b"GET / HTTP/1.1\r\nAccept-Encoding: deflate, GZIP;q=0.5\r\n\r\n",
+        )
+        .unwrap();
+        assert!(request.accepts_encoding("gzip"));
+        assert!(!request.accepts_encoding("br"));
+
+        let request = Request::from_bytes(
+            b"GET / HTTP/1.1\r\nAccept-Encoding: *, gzip;q=0\r\n\r\n",
+        )
+        .unwrap();
+        assert!(request.accepts_encoding("br"));
+        assert!(!request.accepts_encoding("gzip"));
+    }
+
+    #[test]
+    fn redirect_preserve_method() {
+        let response = Response::redirect_preserve_method("/new", true);
+        assert_eq!(*response.status_code(), StatusCode::PermanentRedirect);
+        assert_eq!(response.header_value("location"), Some("/new"));
+        assert!(String::from_utf8(response.serialise())
+            .unwrap()
+            .starts_with("HTTP/1.1 308 Permanent Redirect\r\n"));
+
+        let response = Response::redirect_preserve_method("/later", false);
+        assert_eq!(*response.status_code(), StatusCode::TemporaryRedirect);
+        assert!(String::from_utf8(response.serialise())
+            .unwrap()
+            .starts_with("HTTP/1.1 307 Temporary Redirect\r\n"));
+        assert_eq!(
+            StatusCode::from_u16(307),
+            Some(StatusCode::TemporaryRedirect)
+        );
+        assert_eq!(
+            StatusCode::from_u16(308),
+            Some(StatusCode::PermanentRedirect)
+        );
+    }
+
+    #[test]
+    fn redirect() {
+        let response = Response::redirect("/new", true);
+        assert_eq!(response.header_value("location"), Some("/new"));
+        assert!(String::from_utf8(response.serialise())
+            .unwrap()
+            .starts_with("HTTP/1.1 301 Moved Permanently\r\n"));
+
+        let response = Response::redirect("https://example.com/", false);
+        assert_eq!(
+            response.header_value("location"),
+            Some("https://example.com/")
+        );
+        let response = String::from_utf8(response.serialise()).unwrap();
+        assert!(response.starts_with("HTTP/1.1 302 Found\r\n"));
+        assert!(response.contains("\r\nContent-Length: 0\r\n"));
+
+        let response = Response::see_other("/users/7");
+        assert_eq!(response.header_value("location"), Some("/users/7"));
+        assert!(String::from_utf8(response.serialise())
+            .unwrap()
+            .starts_with("HTTP/1.1 303 See Other\r\n"));
+    }
+
+    #[test]
+    fn build_response() {
+        let response = Response::new()
+            .protocol(Protocol::Http1_0)
+            .status(StatusCode::Created)
+            .header("Location", "/users/7")
+            .body("created");
+        assert_eq!(response.header_value("location"), Some("/users/7"));
+
+        let response = String::from_utf8(response.serialise()).unwrap();
+        assert!(response.starts_with("HTTP/1.0 201 Created\r\n"));
+        assert!(response.contains("\r\nLocation: /users/7\r\n"));
+        assert!(response.contains("\r\nContent-Length: 7\r\n"));
+        assert!(response.ends_with("\r\n\r\ncreated"));
+    }
+
+    #[test]
+    fn bearer_token() {
+        let request = Request::from_bytes(
+            b"GET / HTTP/1.1\r\nAuthorization: bearer   abc.def  \r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(request.bearer_token(), Some("abc.def"));
+
+        let request = Request::from_bytes(
+            b"GET / HTTP/1.1\r\nAuthorization: Basic abc.def\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(request.bearer_token(), None);
+
+        let request = Request::from_bytes(
+            b"GET / HTTP/1.1\r\nAuthorization: Bearer\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(request.bearer_token(), None);
+
+        let request = Request::from_bytes(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(request.bearer_token(), None);
+    }
+
+    #[test]
+    fn parse_cookies() {
+        let request = Request::from_bytes(
+            b"GET / HTTP/1.1\r\nCookie: session=abc123; theme=\"dark\"\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(request.cookie("session"), Some("abc123"));
+        assert_eq!(request.cookie("theme"), Some("dark"));
+        assert_eq!(request.cookie("Session"), None);
+        assert_eq!(request.cookie("missing"), None);
+    }
+
+    #[test]
+    fn set_cookies() {
+        let response = Response::new()
+            .set_cookie(
+                "session",
+                "abc123",
+                CookieAttributes::new()
+                    .path("/")
+                    .http_only()
+                    .secure()
+                    .max_age(3600)
+                    .same_site(crate::SameSite::Strict),
+            )
+            .set_cookie("theme", "dark", CookieAttributes::new());
+
+        let response = String::from_utf8(response.serialise()).unwrap();
+        assert!(response.contains("\r\nSet-Cookie: session=abc123; Path=/; Max-Age=3600; HttpOnly; Secure; SameSite=Strict\r\n"));
+        assert!(response.contains("\r\nSet-Cookie: theme=dark\r\n"));
+    }
+
+    #[test]
+    fn date_header() {
+        let before = crate::date::http_date(SystemTime::now());
+        let response = Response::new().body("hi");
+        let serialised = String::from_utf8(response.serialise()).unwrap();
+        let after = crate::date::http_date(SystemTime::now());
+
+        let date = serialised
+            .lines()
+            .find_map(|line| line.strip_prefix("Date: "))
+            .unwrap();
+        assert!(date == before || date == after);
+        assert_eq!(date.len(), "Sun, 06 Nov 1994 08:49:37 GMT".len());
+        assert!(date.ends_with(" GMT"));
+
+        let response =
+            Response::new().header("date", "Sun, 06 Nov 1994 08:49:37 GMT");
+        let serialised = String::from_utf8(response.serialise()).unwrap();
+        assert_eq!(serialised.matches("ate: ").count(), 1);
+        assert!(
+            serialised.contains("\r\ndate: Sun, 06 Nov 1994 08:49:37 GMT\r\n")
+        );
+    }
+
+    #[test]
+    fn default_headers() {
+        let response = Response::new().body("hi");
+        let serialised = String::from_utf8(response.serialise()).unwrap();
+        assert!(serialised.contains(&format!("\r\nServer: {SERVER}\r\n")));
+        assert!(serialised
+            .contains("\r\nContent-Type: text/plain; charset=utf-8\r\n"));
+
+        let response = Response::new()
+            .header("content-type", "text/html")
+            .header("Server", "custom")
+            .body("<p>hi</p>");
+        let serialised = String::from_utf8(response.serialise()).unwrap();
+        assert_eq!(serialised.matches("ontent-type").count(), 1);
+        assert!(serialised.contains("\r\ncontent-type: text/html\r\n"));
+        assert!(serialised.contains("\r\nServer: custom\r\n"));
+
+        let response = Response::new().status(StatusCode::NoContent);
+        assert!(!String::from_utf8(response.serialise())
+            .unwrap()
+            .contains("Content-Type"));
+
+        let mut response = Response::new().body("hi");
+        response.skip_default_headers();
+        let serialised = String::from_utf8(response.serialise()).unwrap();
+        assert!(!serialised.contains("Server"));
+        assert!(!serialised.contains("Content-Type"));
+    }
+
+    #[test]
+    fn content_length_in_bytes() {
+        let mut response = Response::new().body("héllo");
+        let mut wire = Vec::new();
+        response.write_to(&mut wire, true).unwrap();
+        let wire = String::from_utf8(wire).unwrap();
+        assert!(wire.contains("\r\nContent-Length: 6\r\n"));
+        assert!(wire.ends_with("\r\n\r\nhéllo"));
+    }
+
+    #[test]
+    fn serialise_twice() {
+        // A fixed `Date` so the two calls can't straddle a second
+        let response = Response::new()
+            .header("Date", "Sun, 06 Nov 1994 08:49:37 GMT")
+            .body("héllo");
+        let serialised = response.serialise();
+        assert_eq!(response.serialise(), serialised);
+
+        let serialised = String::from_utf8(serialised).unwrap();
+        assert!(serialised.contains("\r\nContent-Length: 6\r\n"));
+        assert!(serialised.ends_with("\r\n\r\nhéllo"));
+        assert_eq!(response.header_value("Content-Length"), None);
+    }
+
+    #[test]
+    fn response_from_file() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/index.html");
+        let response = Response::from_file(path).unwrap();
+        assert_eq!(
+            response.header_value("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+
+        let etag = response.header_value("ETag").unwrap();
+        assert!(etag.starts_with("W/\"41-") && etag.ends_with('"'));
+
+        let serialised = String::from_utf8(response.serialise()).unwrap();
+        let len = fs::metadata(path).unwrap().len();
+        assert!(serialised.contains(&format!("\r\nContent-Length: {len}\r\n")));
+        assert!(serialised.ends_with("<p>Hello from a file</p>\n"));
+
+        let err = Response::from_file("testdata/missing.html").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn text_and_html() {
+        let response = Response::text(42);
+        assert_eq!(*response.status_code(), StatusCode::Ok);
+        assert_eq!(
+            response.header_value("content-type"),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(response.body_bytes(), b"42");
+
+        let response =
+            Response::html("<h1>Gone</h1>").status(StatusCode::NotFound);
+        assert_eq!(*response.status_code(), StatusCode::NotFound);
+        assert_eq!(
+            response.header_value("content-type"),
+            Some("text/html; charset=utf-8")
+        );
+        let wire = String::from_utf8(response.serialise()).unwrap();
+        assert!(wire.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(wire.ends_with("\r\n\r\n<h1>Gone</h1>"));
+    }
+
+    #[test]
+    fn status_constructors() {
+        let wire = |response: Response| {
+            String::from_utf8(response.serialise()).unwrap()
+        };
+
+        let ok = wire(Response::ok("hi"));
+        assert!(ok.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(ok.ends_with("\r\nContent-Length: 2\r\n\r\nhi"));
+
+        let created = Response::created("/users/1");
+        assert_eq!(created.header_value("location"), Some("/users/1"));
+        assert!(wire(created).starts_with("HTTP/1.1 201 Created\r\n"));
+
+        let no_content = wire(Response::no_content());
+        assert!(no_content.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert!(!no_content.contains("Content-Length"));
+        assert!(!no_content.contains("Content-Type"));
+        assert!(no_content.ends_with("\r\n\r\n"));
+
+        let bad_request = wire(Response::bad_request("missing name"));
+        assert!(bad_request.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(bad_request.ends_with("\r\n\r\nmissing name"));
+
+        let internal_error = wire(Response::internal_error());
+        assert!(internal_error
+            .starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(internal_error.ends_with("\r\n\r\n500 Internal Server Error"));
+    }
+
+    #[test]
+    fn parse_response() {
+        let response = Response::from_bytes(
+            b"HTTP/1.1 201 Created\r\nLocation: /users/1\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(*response.status_code(), StatusCode::Created);
+        assert_eq!(response.header_value("location"), Some("/users/1"));
+        assert_eq!(
+            response.header_values("set-cookie").collect::<Vec<_>>(),
+            ["a=1", "b=2"]
+        );
+        assert_eq!(response.header_value("transfer-encoding"), None);
+        assert_eq!(response.body_bytes(), b"hello");
+
+        let response = Response::from_bytes(
+            b"HTTP/1.0 200 OK\r\nContent-Length: 2\r\n\r\nhi and more",
+        )
+        .unwrap();
+        assert_eq!(response.protocol, Protocol::Http1_0);
+        assert_eq!(response.body_bytes(), b"hi");
+
+        for code in [207, 299, 418] {
+            let raw = format!("HTTP/1.1 {code} Odd\r\n\r\n");
+            let response = Response::from_bytes(raw.as_bytes()).unwrap();
+            assert_eq!(*response.status_code(), StatusCode::Other(code));
+        }
+        let response = Response::from_bytes(b"HTTP/1.1 404 Gone\r\n\r\n");
+        assert_eq!(*response.unwrap().status_code(), StatusCode::NotFound);
+
+        let response = Response::parse(
+            b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n",
+            true,
+        )
+        .unwrap();
+        assert_eq!(response.header_value("content-length"), Some("10"));
+
+        let incomplete = [
+            &b"HTTP/1.1 200 OK\r\n"[..],
+            b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhi",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhi",
+        ];
+        for raw in incomplete {
+            assert_eq!(
+                Response::from_bytes(raw).unwrap_err(),
+                Error::Incomplete
+            );
+        }
+        for raw in [
+            &b"HTTP/1.1 99 Low\r\n\r\n"[..],
+            b"HTTP/1.1 600 High\r\n\r\n",
+            b"HTTP/1.1 +20 Signed\r\n\r\n",
+            b"HTTP/1.1 OK\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nContent-Length: +2\r\n\r\nhi",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffff\r\nhi\r\n0\r\n\r\n",
+        ] {
+            assert_eq!(
+                Response::from_bytes(raw).unwrap_err(),
+                Error::Malformed
+            );
+        }
+        let err = Response::from_bytes(b"HTTP/2 200 OK\r\n\r\n").unwrap_err();
+        assert_eq!(err, Error::InvalidProtocol);
+    }
+
+    #[test]
+    fn response_round_trip() {
+        let response = Response::new()
+            .status(StatusCode::NotFound)
+            .header("X-Test", "yes")
+            .header("Date", "Sun, 06 Nov 1994 08:49:37 GMT")
+            .body("missing");
+        let parsed = Response::from_bytes(&response.serialise()).unwrap();
+        assert_eq!(parsed.serialise(), response.serialise());
+    }
+
+    #[test]
+    fn custom_status() {
+        let response = Response::new()
+            .set_status_code(StatusCode::TooManyRequests)
+            .set_body("slow down");
+        assert_eq!(*response.status_code(), StatusCode::TooManyRequests);
+        assert_eq!(response.body_bytes(), b"slow down");
+        assert!(String::from_utf8(response.serialise())
+            .unwrap()
+            .starts_with("HTTP/1.1 429 Too Many Requests\r\n"));
+    }
+
+    #[test]
+    fn attachment() {
+        let disposition = |filename| {
+            Response::new()
+                .attachment(filename)
+                .header_value("content-disposition")
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(
+            disposition("report.pdf"),
+            "attachment; filename=\"report.pdf\""
+        );
+        assert_eq!(
+            disposition("say \"hi\" \\ bye.txt"),
+            "attachment; filename=\"say \\\"hi\\\" \\\\ bye.txt\""
+        );
+        assert_eq!(
+            disposition("naïve\r\n.txt"),
+            "attachment; filename=\"na_ve__.txt\"; filename*=UTF-8''na%C3%AFve%0D%0A.txt"
+        );
+    }
+
+    #[test]
+    fn hsts() {
+        let response = Response::new().hsts(Duration::from_secs(31_536_000));
+        assert_eq!(
+            response.header_value("strict-transport-security"),
+            Some("max-age=31536000")
+        );
+    }
+
+    #[test]
+    fn content_negotiation() {
+        let request = |accept: &str| {
+            Request::from_bytes(
+                format!("GET / HTTP/1.1\r\nAccept: {accept}\r\n\r\n")
+                    .as_bytes(),
+            )
+            .unwrap()
+        };
+        let offered = ["application/json", "text/html"];
+
+        let browser = request("text/html, application/json;q=0.9");
+        assert!(browser.accepts("text/html"));
+        assert!(browser.accepts("application/json"));
+        assert!(!browser.accepts("image/png"));
+        assert_eq!(browser.preferred(&offered), Some("text/html"));
+
+        let api = request("application/*, */*;q=0.1");
+        assert_eq!(api.preferred(&offered), Some("application/json"));
+        assert!(api.accepts("image/png"));
+
+        let refuses = request("text/*;q=0, text/html; charset=utf-8");
+        assert!(!refuses.accepts("text/plain"));
+        assert_eq!(
+            refuses.preferred(&["text/plain", "text/html; charset=utf-8"]),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(request("image/*").preferred(&offered), None);
+
+        let anything = Request::from_bytes(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(anything.preferred(&offered), Some("application/json"));
+    }
+
+    #[test]
+    fn content_length() {
+        let parse = |headers: &str| {
+            Request::from_bytes(
+                format!("POST / HTTP/1.1\r\n{headers}\r\n").as_bytes(),
+            )
+        };
+
+        let request = parse("Content-Length: 5\r\n").unwrap();
+        assert_eq!(request.content_length(), Some(5));
+        assert_eq!(request.content_len(), 5);
+        let request = parse("Content-Length: 5, 5\r\nContent-Length: 5\r\n");
+        assert_eq!(request.unwrap().content_length(), Some(5));
+
+        let request = parse("").unwrap();
+        assert_eq!(request.content_length(), None);
+        assert_eq!(request.content_len(), 0);
+
+        for malformed in [
+            "Content-Length: five\r\n",
+            "Content-Length: -1\r\n",
+            "Content-Length: +5\r\n",
+            "Content-Length: 5, +5\r\n",
+            "Content-Length: \r\n",
+            "Content-Length: 5\r\nContent-Length: 6\r\n",
+            "Content-Length: 5, 6\r\n",
+            "Content-Length: 5\r\nTransfer-Encoding: chunked\r\n",
+            "Transfer-Encoding: chunked\r\nContent-Length: 0\r\n",
+        ] {
+            assert_eq!(parse(malformed).unwrap_err(), Error::Malformed);
+        }
+    }
+
+    #[test]
+    fn transfer_codings() {
+        let parse = |codings: &str| {
+            Request::from_bytes(
+                format!(
+                    "POST / HTTP/1.1\r\nTransfer-Encoding: {codings}\r\n\r\n"
+                )
+                .as_bytes(),
+            )
+        };
+
+        assert!(parse("chunked").unwrap().chunked());
+        assert!(parse("gzip, Chunked").unwrap().chunked());
+
+        for malformed in ["gzip", "chunked, gzip", "chunked, chunked", ""] {
+            assert_eq!(parse(malformed).unwrap_err(), Error::Malformed);
+        }
+    }
+
+    #[test]
+    fn request_uri() {
+        let request = Request::from_bytes(
+            b"GET /search?q=rust+lang&tag=a&tag=b HTTP/1.1\r\n\r\n",
+        )
+        .unwrap();
+        let uri = request.uri();
+        assert_eq!(uri.path(), "/search");
+        assert_eq!(uri.query(), Some("q=rust+lang&tag=a&tag=b"));
+        assert_eq!(
+            uri.query_pairs(),
+            [
+                ("q".into(), "rust lang".into()),
+                ("tag".into(), "a".into()),
+                ("tag".into(), "b".into()),
+            ]
+        );
+        // The map keeps the last of a repeated name
+        assert_eq!(request.query("tag"), Some("b"));
     }
 }
@@ -1,204 +1,4349 @@
+mod access_log;
+pub mod auth;
+mod chunked;
+mod client;
+mod cookie;
+mod cors;
+mod date;
+#[cfg(feature = "gzip")]
+mod gzip;
 mod http;
-pub use http::{Method, Request, Response, StatusCode};
+mod middleware;
+pub mod mime;
+mod multipart;
+mod pool;
+mod proxy;
+pub mod rate_limit;
+mod router;
+pub mod sse;
+mod uri;
+pub mod websocket;
+pub use client::Client;
+pub use cookie::{CookieAttributes, SameSite};
+pub use cors::CorsConfig;
+pub use http::{
+    Error, HttpError, Method, Protocol, Request, Response, StatusCode,
+};
+pub use middleware::Next;
+pub use multipart::Part;
+pub use uri::Uri;
 
+/// A plain function handler, any `Fn(Request) -> Response` closure can be
+/// registered as well
 pub type Handler = fn(Request) -> Response;
 
+/// A handler that can fail, errors are turned into responses by the
+/// [`ServerBuilder::error_handler`]
+pub type TryHandler = fn(Request) -> Result<Response, HttpError>;
+
 use std::{
-    collections::HashMap,
     io::{Read, Write},
-    net::{TcpListener, TcpStream, ToSocketAddrs},
-    str,
-    sync::Arc,
-    thread,
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
     time::Duration,
 };
 
+use access_log::{AccessLog, Entry};
+use chunked::ChunkedDecoder;
+use http::normalise_path;
+use middleware::BoxedMiddleware;
+use pool::ThreadPool;
+pub use router::{Endpoint, Router};
+
+/// How long the accept loop of a spawned server sleeps when there are no new
+/// connections before checking whether it has been shut down
+const SHUTDOWN_POLL: Duration = Duration::from_millis(10);
+
+/// The first pause after a transient accept error, doubled for each one in a
+/// row up to [`MAX_ACCEPT_BACKOFF`]
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(5);
+
+/// The longest pause between accepts while they keep failing
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The largest request body accepted unless configured otherwise
+const DEFAULT_MAX_BODY: usize = 2 * 1024 * 1024;
+
+/// How long to wait on a plaintext connection unless configured otherwise
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(4000);
+
+/// How long a kept alive connection can sit idle between requests unless
+/// configured otherwise
+const DEFAULT_KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many requests a connection can make before it is closed unless
+/// configured otherwise
+const DEFAULT_MAX_REQUESTS: usize = 100;
+
+/// How much the read buffer grows by while looking for the end of the headers
+const HEADER_READ_BYTES: usize = 1024;
+
+/// How much the read buffer grows by while reading a chunked body
+const CHUNKED_READ_BYTES: usize = 16 * 1024;
+
+/// Requests with more header bytes than this are rejected unless configured
+/// otherwise
+const DEFAULT_MAX_HEADER_BYTES: usize = 16 * 1024;
+
+/// Why a server couldn't be started
+#[derive(Debug)]
+pub enum BindError {
+    /// Another socket is already listening on the address
+    AddrInUse(std::io::Error),
+    /// The process isn't allowed to listen on the address, such as a port
+    /// below 1024 without privileges
+    PermissionDenied(std::io::Error),
+    /// Listening on the address failed for another reason
+    Io(std::io::Error),
+    /// The certificate chain couldn't be parsed
+    InvalidCert(String),
+    /// The private key couldn't be parsed or doesn't suit the certificate
+    InvalidKey(String),
+}
+
+impl std::fmt::Display for BindError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::AddrInUse(err) => write!(f, "address in use: {err}"),
+            Self::PermissionDenied(err) => {
+                write!(f, "permission denied: {err}")
+            }
+            Self::Io(err) => write!(f, "failed to listen: {err}"),
+            Self::InvalidCert(reason) => {
+                write!(f, "invalid certificate: {reason}")
+            }
+            Self::InvalidKey(reason) => {
+                write!(f, "invalid private key: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for BindError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::AddrInUse(err)
+            | Self::PermissionDenied(err)
+            | Self::Io(err) => Some(err),
+            Self::InvalidCert(_) | Self::InvalidKey(_) => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for BindError {
+    fn from(err: std::io::Error) -> Self {
+        match err.kind() {
+            std::io::ErrorKind::AddrInUse => Self::AddrInUse(err),
+            std::io::ErrorKind::PermissionDenied => Self::PermissionDenied(err),
+            _ => Self::Io(err),
+        }
+    }
+}
+
 pub struct Server;
 
 impl Server {
-    pub fn bind(addr: impl ToSocketAddrs) -> ServerBuilder {
-        ServerBuilder {
-            listener: TcpListener::bind(addr).unwrap(),
-            paths: HashMap::new(),
-            default: not_found,
+    /// Listen on `addr`, such as `0.0.0.0:8080`, call
+    /// [`ServerBuilder::listen`] once the routes are registered
+    pub fn bind(addr: impl ToSocketAddrs) -> Result<ServerBuilder, BindError> {
+        Ok(ServerBuilder::new(vec![TcpListener::bind(addr)?]))
+    }
+
+    /// Listen on every address `addrs` resolve to at once, such as
+    /// `["0.0.0.0:8080", "[::]:8080"]` for both IPv4 and IPv6. Where an IPv6
+    /// socket on the unspecified address already takes IPv4 connections for
+    /// its port, as on Linux, the matching IPv4 address is left to it.
+    pub fn bind_all(
+        addrs: impl IntoIterator<Item = impl ToSocketAddrs>,
+    ) -> Result<ServerBuilder, BindError> {
+        Ok(ServerBuilder::new(bind_listeners(addrs)?))
+    }
+}
+
+/// A listener for each address `addrs` resolve to, IPv6 first so dual-stack
+/// sockets can stand in for the IPv4 ones they overlap
+fn bind_listeners(
+    addrs: impl IntoIterator<Item = impl ToSocketAddrs>,
+) -> Result<Vec<TcpListener>, BindError> {
+    let mut resolved = Vec::new();
+    for addr in addrs {
+        resolved.extend(addr.to_socket_addrs()?);
+    }
+    resolved.sort_by_key(|addr| addr.is_ipv4());
+    resolved.dedup();
+
+    let mut listeners: Vec<TcpListener> = Vec::new();
+    for addr in resolved {
+        let err = match TcpListener::bind(addr) {
+            Ok(listener) => {
+                listeners.push(listener);
+                continue;
+            }
+            Err(err) => err,
+        };
+        let dual_stack = addr.is_ipv4()
+            && err.kind() == std::io::ErrorKind::AddrInUse
+            && listeners.iter().any(|listener| {
+                listener.local_addr().is_ok_and(|bound| {
+                    bound.ip().is_unspecified()
+                        && bound.is_ipv6()
+                        && bound.port() == addr.port()
+                })
+            });
+        if !dual_stack {
+            return Err(err.into());
+        }
+        log::debug!("{addr} is served by the IPv6 listener on its port");
+    }
+    if listeners.is_empty() {
+        return Err(BindError::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "no addresses to listen on",
+        )));
+    }
+    Ok(listeners)
+}
+
+impl ServerBuilder {
+    fn new(listeners: Vec<TcpListener>) -> Self {
+        Self {
+            listeners,
+            router: Router::new(),
+            body_limits: Router::new(),
+            max_body: DEFAULT_MAX_BODY,
+            max_header_bytes: DEFAULT_MAX_HEADER_BYTES,
+            read_timeout: Some(DEFAULT_TIMEOUT),
+            write_timeout: Some(DEFAULT_TIMEOUT),
+            keep_alive_timeout: Some(DEFAULT_KEEP_ALIVE_TIMEOUT),
+            max_requests: DEFAULT_MAX_REQUESTS,
+            workers: thread::available_parallelism()
+                .map(|workers| workers.get())
+                .unwrap_or(1),
+            on_request: None,
+            on_error: None,
+            on_accept_error: None,
+            on_response: None,
+            on_panic: None,
+            error_handler: error_response,
+            middleware: Vec::new(),
+            cors: None,
+            default_headers: true,
+            auto_options: true,
+            auto_head: true,
+            trust_forwarded_for: false,
+            method_override: false,
+            https_redirect: false,
+            request_ids: false,
+            max_connections: None,
+            access_log: None,
+            connections: Arc::new(AtomicUsize::new(0)),
+            #[cfg(feature = "gzip")]
+            compression: false,
         }
     }
 }
+
 pub struct ServerBuilder {
-    listener: TcpListener,
-    paths: HashMap<String, Handler>,
-    default: Handler,
+    /// Never empty
+    listeners: Vec<TcpListener>,
+    router: Router,
+    /// Paths given their own largest body, matched like routes but kept
+    /// apart so they never serve a request
+    body_limits: Router,
+    max_body: usize,
+    max_header_bytes: usize,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    keep_alive_timeout: Option<Duration>,
+    max_requests: usize,
+    workers: usize,
+    on_request: Option<fn(&Request)>,
+    on_error: Option<fn(&Error)>,
+    on_accept_error: Option<fn(&std::io::Error)>,
+    on_response: Option<fn(StatusCode, usize)>,
+    on_panic: Option<fn(&str)>,
+    error_handler: fn(HttpError) -> Response,
+    middleware: Vec<BoxedMiddleware>,
+    /// Runs ahead of all the `middleware`, however it was added
+    cors: Option<CorsConfig>,
+    default_headers: bool,
+    auto_options: bool,
+    auto_head: bool,
+    trust_forwarded_for: bool,
+    method_override: bool,
+    https_redirect: bool,
+    request_ids: bool,
+    max_connections: Option<usize>,
+    access_log: Option<AccessLog>,
+    /// How many connections are open or waiting for a worker
+    connections: Arc<AtomicUsize>,
+    #[cfg(feature = "gzip")]
+    compression: bool,
 }
 
 impl ServerBuilder {
-    pub fn path(mut self, path: &str, handler: Handler) -> Self {
-        self.paths
-            .insert(path.trim_end_matches('/').into(), handler);
+    /// Serve `path` with `handler` regardless of the request method. Segments
+    /// starting with `:` match any value and a final segment starting with `*`
+    /// matches the rest of the path, the handler can read either with
+    /// [`Request::param`]. The path is normalised the same way as
+    /// [`Request::path`], so `/users/` is `/users` and `/` is the root.
+    pub fn path(
+        mut self,
+        path: &str,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.router.route_mut(path).any =
+            Some(Endpoint::Handler(Arc::new(handler)));
+        self
+    }
+
+    /// Serve `path` with `handler` only for requests using `method`
+    pub fn route(
+        mut self,
+        method: Method,
+        path: &str,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.router
+            .route_mut(path)
+            .methods
+            .insert(method, Endpoint::Handler(Arc::new(handler)));
         self
     }
 
-    pub fn listen(self) {
-        let paths = Arc::new(self.paths);
+    /// Like [`ServerBuilder::path`] for a handler that can fail
+    pub fn try_path(
+        mut self,
+        path: &str,
+        handler: impl Fn(Request) -> Result<Response, HttpError>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.router.route_mut(path).any =
+            Some(Endpoint::TryHandler(Arc::new(handler)));
+        self
+    }
 
-        for stream in self.listener.incoming() {
-            let paths_clone = paths.clone();
-            match stream {
-                Ok(stream) => {
-                    thread::spawn(move || {
-                        Self::handle(stream, paths_clone, self.default)
-                    });
-                }
-                Err(err) => println!("{err:?}"),
-            };
+    /// Like [`ServerBuilder::route`] for a handler that can fail
+    pub fn try_route(
+        mut self,
+        method: Method,
+        path: &str,
+        handler: impl Fn(Request) -> Result<Response, HttpError>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.router
+            .route_mut(path)
+            .methods
+            .insert(method, Endpoint::TryHandler(Arc::new(handler)));
+        self
+    }
+
+    pub fn get(
+        self,
+        path: &str,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route(Method::Get, path, handler)
+    }
+
+    pub fn post(
+        self,
+        path: &str,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route(Method::Post, path, handler)
+    }
+
+    pub fn put(
+        self,
+        path: &str,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route(Method::Put, path, handler)
+    }
+
+    pub fn patch(
+        self,
+        path: &str,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route(Method::Patch, path, handler)
+    }
+
+    pub fn delete(
+        self,
+        path: &str,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route(Method::Delete, path, handler)
+    }
+
+    /// Register handlers that are given a shared `state` as well as the
+    /// request, call [`StateBuilder::finish`] to get back to this builder
+    pub fn with_state<S: Send + Sync + 'static>(
+        self,
+        state: S,
+    ) -> StateBuilder<S> {
+        StateBuilder {
+            builder: self,
+            state: Arc::new(state),
+        }
+    }
+
+    /// Forward requests under `prefix` to the server at `upstream`, such as
+    /// `http://127.0.0.1:9000`, and relay its responses. The prefix is
+    /// swapped for the upstream's path so `/api/users` proxied from `/api`
+    /// is sent on as `/users`. The method, body and headers go along except
+    /// those only meant for one connection, and the client is added to
+    /// `X-Forwarded-For`. Panics unless `upstream` is a plain `http://` URL.
+    pub fn proxy(self, prefix: &str, upstream: &str) -> Self {
+        let prefix = normalise_path(prefix)
+            .unwrap_or_else(|| panic!("{prefix} climbs above the root"));
+        let upstream = Arc::new(proxy::Upstream::new(&prefix, upstream));
+        let nested = upstream.clone();
+        self.path(&prefix, move |request| upstream.forward(&request))
+            .path(&format!("{prefix}/*path"), move |request| {
+                nested.forward(&request)
+            })
+    }
+
+    /// Register handlers under a common `prefix`, so `/users` in a group for
+    /// `/api/v1` is served at `/api/v1/users`. Call [`GroupBuilder::finish`]
+    /// to get back to this builder.
+    pub fn group(self, prefix: &str) -> GroupBuilder {
+        let prefix = prefix.trim_end_matches('/');
+        let prefix = if prefix.is_empty() || prefix.starts_with('/') {
+            prefix.to_string()
+        } else {
+            format!("/{prefix}")
+        };
+        GroupBuilder {
+            builder: self,
+            prefix,
+        }
+    }
+
+    /// Answer `raw`, the bytes of a request, through the middleware and the
+    /// matching handler without a socket, for testing handlers. The response
+    /// is as the handler left it, before the server adds the `Connection`
+    /// header or compresses it, and one that can't be parsed gets the status
+    /// the server would send.
+    pub fn test_request(&self, raw: impl AsRef<[u8]>) -> Response {
+        match Request::parse(raw.as_ref()) {
+            Ok((mut request, _)) => {
+                request.set_secure(false, self.trust_forwarded_for);
+                self.assign_id(&mut request);
+                self.dispatch(request)
+            }
+            Err(err) => status_response(err.status_code()),
+        }
+    }
+
+    /// Serve connections until accepting them fails for good, such as the
+    /// listening socket breaking, returning that error. Transient errors like
+    /// running out of file descriptors are retried after a pause.
+    pub fn listen(self) -> std::io::Result<()> {
+        let server = Arc::new(self);
+        let pool = ThreadPool::new(server.workers);
+
+        accept_all(&server.listeners, server.on_accept_error, |stream| {
+            Self::accept(&server, &pool, stream)
+        })
+    }
+
+    /// The addresses the server is listening on, useful after binding port 0
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .iter()
+            .filter_map(|listener| listener.local_addr().ok())
+            .collect()
+    }
+
+    /// Serve connections on a background thread until [`ServerHandle::shutdown`]
+    /// is called. Fails if the listeners can't be switched to polling.
+    pub fn spawn(self) -> std::io::Result<ServerHandle> {
+        let addr = self.listeners[0].local_addr()?;
+        for listener in &self.listeners {
+            listener.set_nonblocking(true)?;
         }
+
+        let running = Arc::new(AtomicBool::new(true));
+        let running_clone = running.clone();
+        let thread = thread::spawn(move || {
+            let server = Arc::new(self);
+            let pool = ThreadPool::new(server.workers);
+
+            // Take turns between the listeners, starting after the one that
+            // last had a connection
+            let mut next = 0;
+            let incoming = std::iter::from_fn(|| {
+                let listeners = &server.listeners;
+                while running_clone.load(Ordering::SeqCst) {
+                    for _ in 0..listeners.len() {
+                        let listener = &listeners[next % listeners.len()];
+                        next = next.wrapping_add(1);
+                        match listener.accept() {
+                            Err(ref e)
+                                if e.kind()
+                                    == std::io::ErrorKind::WouldBlock => {}
+                            accepted => {
+                                return Some(accepted.map(|(stream, _)| stream))
+                            }
+                        }
+                    }
+                    thread::sleep(SHUTDOWN_POLL);
+                }
+                None
+            });
+            // A fatal error is logged by the loop, shutting down then has
+            // nothing left to stop
+            let _ = accept_loop(incoming, server.on_accept_error, |stream| {
+                if let Err(err) = stream.set_nonblocking(false) {
+                    log::warn!("dropping connection: {err}");
+                    return;
+                }
+                Self::accept(&server, &pool, stream);
+            });
+        });
+
+        Ok(ServerHandle {
+            addr,
+            running,
+            thread,
+        })
     }
 
     /// The default response the web server will serve if their is no matching path
-    pub fn default(mut self, handler: Handler) -> Self {
-        self.default = handler;
+    pub fn default(
+        mut self,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.router.default = Endpoint::Handler(Arc::new(handler));
         self
     }
 
-    fn handle(
-        mut stream: TcpStream,
-        paths: Arc<HashMap<String, Handler>>,
-        default: Handler,
-    ) {
-        set_stream_timeouts(&stream, Duration::from_millis(4000));
+    /// Route requests with `router`, replacing any routes and default handler
+    /// registered so far
+    pub fn router(mut self, router: Router) -> Self {
+        self.router = router;
+        self
+    }
+
+    /// The largest `Content-Length` in bytes the server will accept, larger
+    /// requests are rejected with `413 Payload Too Large`
+    pub fn max_body(mut self, bytes: usize) -> Self {
+        self.max_body = bytes;
+        self
+    }
+
+    /// Like [`ServerBuilder::max_body`] for requests to `path` alone, such as
+    /// an upload endpoint that needs more room than the rest of the API. The
+    /// route is matched on the request's headers, before its body is read.
+    pub fn route_max_body(mut self, path: &str, bytes: usize) -> Self {
+        self.body_limits.route_mut(path).max_body = Some(bytes);
+        self
+    }
+
+    /// The most bytes the request line and headers can take up, defaults to
+    /// 16KiB. Larger requests are rejected with
+    /// `431 Request Header Fields Too Large`.
+    pub fn max_header_bytes(mut self, bytes: usize) -> Self {
+        self.max_header_bytes = bytes;
+        self
+    }
+
+    /// The number of threads serving connections, defaults to the number of
+    /// logical CPUs
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    /// How long to wait for the client to send data before closing the
+    /// connection, `None` waits forever
+    pub fn read_timeout(
+        mut self,
+        timeout: impl Into<Option<Duration>>,
+    ) -> Self {
+        self.read_timeout = timeout.into();
+        self
+    }
+
+    /// How long to wait for the client to accept data before closing the
+    /// connection, `None` waits forever
+    pub fn write_timeout(
+        mut self,
+        timeout: impl Into<Option<Duration>>,
+    ) -> Self {
+        self.write_timeout = timeout.into();
+        self
+    }
+
+    /// How long a kept alive connection can wait for its next request before
+    /// it is closed, defaults to 5 seconds. `None` waits forever.
+    pub fn keep_alive_timeout(
+        mut self,
+        timeout: impl Into<Option<Duration>>,
+    ) -> Self {
+        self.keep_alive_timeout = timeout.into();
+        self
+    }
+
+    /// How many requests a connection can make before the server closes it,
+    /// answering the last with `Connection: close`. Defaults to 100, and the
+    /// first request is always answered.
+    pub fn max_requests_per_connection(mut self, requests: usize) -> Self {
+        self.max_requests = requests;
+        self
+    }
+
+    /// Called with every request before it is dispatched to a handler
+    pub fn on_request(mut self, hook: fn(&Request)) -> Self {
+        self.on_request = Some(hook);
+        self
+    }
+
+    /// Called when a request could not be parsed, before the client is sent an
+    /// error response
+    pub fn on_error(mut self, hook: fn(&Error)) -> Self {
+        self.on_error = Some(hook);
+        self
+    }
+
+    /// Called when accepting a connection fails, both for errors that are
+    /// retried after a pause and the one that stops the server
+    pub fn on_accept_error(mut self, hook: fn(&std::io::Error)) -> Self {
+        self.on_accept_error = Some(hook);
+        self
+    }
+
+    /// Called after every response is sent with its status and the number of
+    /// body bytes written. That is the compressed size of gzipped bodies and
+    /// the sum of the chunks of streamed ones, not counting chunk framing.
+    pub fn on_response(mut self, hook: fn(StatusCode, usize)) -> Self {
+        self.on_response = Some(hook);
+        self
+    }
 
-        let mut recv_buf = [0u8; u16::MAX as usize];
+    /// Called with the panic message when a handler or middleware panics. The
+    /// worker carries on and the client is sent a
+    /// `500 Internal Server Error` from the
+    /// [`ServerBuilder::error_handler`]. A panic while a streamed body,
+    /// trailers or an upgraded connection is being served is too late for
+    /// that, so the connection is closed instead.
+    pub fn on_panic(mut self, hook: fn(&str)) -> Self {
+        self.on_panic = Some(hook);
+        self
+    }
+
+    /// Turns the errors returned by fallible handlers into responses, by
+    /// default the status and message are sent as text
+    pub fn error_handler(mut self, handler: fn(HttpError) -> Response) -> Self {
+        self.error_handler = handler;
+        self
+    }
+
+    /// Wrap every request in `middleware`, which can change the request before
+    /// passing it on with [`Next::run`] and change the response that comes
+    /// back, or respond itself without calling the handler. Middleware runs in
+    /// the order it is registered.
+    pub fn middleware(
+        mut self,
+        middleware: impl Fn(Request, Next) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Gzip response bodies for clients sending `Accept-Encoding: gzip`,
+    /// skipping short bodies and types such as images that are compressed
+    /// already
+    #[cfg(feature = "gzip")]
+    pub fn compression(mut self, enabled: bool) -> Self {
+        self.compression = enabled;
+        self
+    }
+
+    /// Whether responses get a `Server` header and, for a body without a
+    /// type, `Content-Type: text/plain; charset=utf-8` when the handler
+    /// didn't set them. On by default.
+    pub fn default_headers(mut self, enabled: bool) -> Self {
+        self.default_headers = enabled;
+        self
+    }
+
+    /// Whether `OPTIONS` requests for a path without an `OPTIONS` handler
+    /// are answered with a `204 No Content` listing the path's methods in
+    /// `Allow`, and `OPTIONS *` with every method the server handles. On by
+    /// default.
+    pub fn auto_options(mut self, enabled: bool) -> Self {
+        self.auto_options = enabled;
+        self
+    }
+
+    /// Whether `HEAD` requests for a path without a `HEAD` handler are served
+    /// by its `GET` handler, with the body left off. Otherwise they get a
+    /// `405 Method Not Allowed`. On by default.
+    pub fn auto_head(mut self, enabled: bool) -> Self {
+        self.auto_head = enabled;
+        self
+    }
+
+    /// Whether [`Request::client_ip`] is taken from the `X-Forwarded-For`
+    /// header and [`Request::is_secure`] from `X-Forwarded-Proto`, only safe
+    /// behind a proxy that sets them. Off by default.
+    pub fn trust_forwarded_for(mut self, trusted: bool) -> Self {
+        self.trust_forwarded_for = trusted;
+        self
+    }
+
+    /// Route `POST` requests with an `X-HTTP-Method-Override` header of
+    /// `PUT`, `PATCH` or `DELETE` as that method, for clients that can only
+    /// send `GET` and `POST`. Other overrides are ignored. Off by default.
+    pub fn method_override(mut self, enabled: bool) -> Self {
+        self.method_override = enabled;
+        self
+    }
+
+    /// Answer every request that isn't [`Request::is_secure`] with a
+    /// `301 Moved Permanently` to the same host and path over `https://` on
+    /// the default port, before any middleware runs. Requests without a
+    /// `Host` get a `400 Bad Request`. Off by default.
+    pub fn https_redirect(mut self, enabled: bool) -> Self {
+        self.https_redirect = enabled;
+        self
+    }
+
+    /// Give every request an ID, see [`Request::id`], and send it back in an
+    /// `X-Request-Id` header. One the client or a proxy sent is kept if it is
+    /// at most 128 printable ASCII characters. Off by default.
+    pub fn request_ids(mut self, enabled: bool) -> Self {
+        self.request_ids = enabled;
+        self
+    }
 
-        let mut len = match stream.read(&mut recv_buf) {
-            Ok(len) => len,
-            Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => return,
-            Err(e) => panic!("{}", e),
+    /// Let each client make at most `max` requests every `per`, answering any
+    /// more with `429 Too Many Requests` and a `Retry-After`. Clients are told
+    /// apart by [`Request::client_ip`], see [`rate_limit::limit`] for
+    /// limiting a single route.
+    pub fn rate_limit(mut self, max: u32, per: Duration) -> Self {
+        let limiter = rate_limit::RateLimiter::new(max, per);
+        self.middleware.insert(
+            0,
+            Arc::new(move |request, next| match limiter.check(&request) {
+                Ok(()) => next.run(request),
+                Err(response) => response,
+            }),
+        );
+        self
+    }
+
+    /// Answer CORS preflight requests and add `Access-Control-Allow-Origin`
+    /// to responses for the origins `config` allows, replacing any earlier
+    /// config. This runs ahead of any other middleware, including the
+    /// [`ServerBuilder::rate_limit`], as preflight requests don't carry
+    /// credentials and would otherwise be turned away by authentication.
+    pub fn cors(mut self, config: CorsConfig) -> Self {
+        self.cors = Some(config);
+        self
+    }
+
+    /// The most connections served at once, including those waiting for a
+    /// worker. Any more are answered with `503 Service Unavailable` and
+    /// closed. Unlimited by default.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Write a line in the Common Log Format to `writer` for every response,
+    /// such as `127.0.0.1 - - [06/Nov/1994:08:49:37 +0000] "GET /users
+    /// HTTP/1.1" 200 10`
+    pub fn access_log(mut self, writer: impl Write + Send + 'static) -> Self {
+        self.access_log = Some(AccessLog::new(writer));
+        self
+    }
+
+    /// Queue `stream` for a worker, or turn it away if too many connections
+    /// are open
+    fn accept(server: &Arc<Self>, pool: &ThreadPool, stream: TcpStream) {
+        let Some(slot) = server.open_connection() else {
+            log::warn!("too many connections, refusing one");
+            refuse(stream, server.write_timeout);
+            return;
         };
+        let server = server.clone();
+        pool.execute(move || {
+            server.handle(stream);
+            drop(slot);
+        });
+    }
+
+    /// Count a new connection as open until the slot is dropped, `None` if
+    /// `max_connections` are open already
+    fn open_connection(&self) -> Option<ConnectionSlot> {
+        let open = self.connections.fetch_add(1, Ordering::SeqCst);
+        let slot = ConnectionSlot(self.connections.clone());
+        self.max_connections
+            .is_none_or(|max| open < max)
+            .then_some(slot)
+    }
+
+    fn handle(&self, mut stream: TcpStream) {
+        set_stream_timeouts(&stream, self.read_timeout, self.write_timeout);
+        let peer_addr = stream.peer_addr().ok();
+        self.serve(&mut stream, peer_addr);
 
-        let mut request = Request::from_bytes(&recv_buf[..len]);
+        // Signal the end of the last response rather than leaving it to the
+        // drop, which can cut off what the client hasn't read yet
+        let _ = stream.flush();
+        let _ = stream.shutdown(Shutdown::Write);
+    }
 
-        let content_len = request.content_len();
+    /// Answer requests on `stream` until the client or a response closes the
+    /// connection, shared by plain and TLS connections
+    fn serve(
+        &self,
+        stream: &mut impl Connection,
+        peer_addr: Option<SocketAddr>,
+    ) {
+        // Bytes read past the end of a request, the start of the next one
+        // when the client pipelines requests
+        let mut buf = Vec::new();
+        let mut requests = 0;
         loop {
-            if content_len <= request.body().len() {
-                break;
+            if requests > 0
+                && buf.is_empty()
+                && !self.await_request(stream, &mut buf)
+            {
+                return;
             }
+            requests += 1;
 
-            let next_len = match stream.read(&mut recv_buf[len..]) {
-                Ok(len) => len,
-                Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => {
-                    return
+            let mut request = match read_request(
+                stream,
+                &mut buf,
+                self.max_header_bytes,
+                |request| self.body_limit(request),
+            ) {
+                Ok(request) => request,
+                Err(ReadError::Disconnected) => return,
+                Err(ReadError::TimedOut) => {
+                    log::debug!("timed out waiting for a request");
+                    return;
+                }
+                Err(ReadError::Invalid(err)) => {
+                    log::debug!("invalid request: {err}");
+                    if let Some(on_error) = self.on_error {
+                        on_error(&err);
+                    }
+                    let mut response = status_response(err.status_code())
+                        .header("Connection", "close");
+                    if !self.default_headers {
+                        response.skip_default_headers();
+                    }
+                    let _ = response
+                        .write_to(stream, true)
+                        .and_then(|_| stream.flush());
+                    return;
                 }
-                Err(e) => panic!("{}", e),
             };
-            let body = str::from_utf8(&recv_buf[len..len + next_len]).unwrap();
-            request.body_mut().push_str(body);
-            len += next_len;
-        }
 
-        let mut response: Response = match paths.get(request.path()) {
-            Some(handler) => handler(request),
-            None => default(request),
-        };
+            request.set_peer_addr(peer_addr, self.trust_forwarded_for);
+            request.set_secure(stream.secure(), self.trust_forwarded_for);
+            self.assign_id(&mut request);
+            log::debug!(
+                "{} {}",
+                <&str>::from(*request.method()),
+                request.path()
+            );
+            if let Some(on_request) = self.on_request {
+                on_request(&request);
+            }
+
+            let entry = self.access_log.is_some().then(|| Entry::new(&request));
+            let keep_alive = request.keep_alive();
+            // Answer with the client's protocol, or the newest it understands
+            let protocol = match request.protocol() {
+                Protocol::Http1_1 => Protocol::Http1_1,
+                Protocol::Http1_0 | Protocol::Http0_9 => Protocol::Http1_0,
+            };
+            let include_body = *request.method() != Method::Head;
+            let if_none_match =
+                matches!(request.method(), Method::Get | Method::Head)
+                    .then(|| request.header("if-none-match"))
+                    .flatten()
+                    .map(str::to_string);
+            let range = (*request.method() == Method::Get)
+                .then(|| request.header("range"))
+                .flatten()
+                .map(|range| {
+                    (
+                        range.to_string(),
+                        request.header("if-range").map(str::to_string),
+                    )
+                });
+            #[cfg(feature = "gzip")]
+            let gzip = self.compression && request.accepts_encoding("gzip");
+            let mut response = self.dispatch(request).protocol(protocol);
+            if let Some(upgrade) = response.take_upgrade() {
+                if !self.default_headers {
+                    response.skip_default_headers();
+                }
+                let written = response
+                    .write_to(stream, true)
+                    .and_then(|_| stream.flush());
+                if written.is_ok() {
+                    // The client can send its first frames straight after
+                    // the handshake, those already read go first
+                    let mut stream = Replay {
+                        buffered: std::mem::take(&mut buf),
+                        read: 0,
+                        stream,
+                    };
+                    self.catch_panic(|| upgrade(&mut stream));
+                }
+                return;
+            }
+            if let Some(if_none_match) = &if_none_match {
+                response.not_modified(if_none_match);
+            }
+            if let Some((range, if_range)) = &range {
+                response.range(range, if_range.as_deref());
+            }
+            #[cfg(feature = "gzip")]
+            if gzip {
+                response.compress();
+            }
+            // Without chunked encoding a stream can only end by closing
+            let keep_alive = keep_alive
+                && requests < self.max_requests
+                && !(protocol == Protocol::Http1_0 && response.streamed())
+                && !response
+                    .header_value("Connection")
+                    .is_some_and(|value| value.eq_ignore_ascii_case("close"));
+            response = response.header(
+                "Connection",
+                if keep_alive { "keep-alive" } else { "close" },
+            );
+            if !self.default_headers {
+                response.skip_default_headers();
+            }
 
-        stream.write(response.serialise().as_bytes()).unwrap();
+            // A stream or trailers can panic part way through the body, the
+            // connection is closed as the client can't tell where it ends
+            let written = self
+                .catch_panic(|| {
+                    response
+                        .write_to(stream, include_body)
+                        .and_then(|written| stream.flush().map(|()| written))
+                })
+                .unwrap_or_else(|| Err(std::io::ErrorKind::Other.into()));
+            if let (Some(access_log), Some(entry), Ok(written)) =
+                (&self.access_log, &entry, &written)
+            {
+                access_log.write(entry, *response.status_code(), *written);
+            }
+            if let (Some(on_response), Ok(written)) =
+                (self.on_response, &written)
+            {
+                on_response(*response.status_code(), *written);
+            }
+            if written.is_err() || !keep_alive {
+                return;
+            }
+        }
     }
-}
 
-fn set_stream_timeouts(stream: &TcpStream, duration: Duration) {
-    stream.set_read_timeout(Some(duration)).unwrap();
-    stream.set_write_timeout(Some(duration)).unwrap();
-}
+    /// The largest body accepted for `request`, its route's limit if it has one
+    fn body_limit(&self, request: &Request) -> usize {
+        self.body_limits
+            .find(request.path())
+            .and_then(|(route, _)| route.max_body)
+            .unwrap_or(self.max_body)
+    }
 
-fn not_found(_: Request) -> Response {
-    Response::new()
-        .set_status_code(http::StatusCode::NotFound)
-        .set_body("404 Not Found\nOops! Looks like Nessie took our page for a swim in the Loch")
-}
+    /// Wait up to the keep-alive timeout for the start of the next request on
+    /// an idle connection, false if it never came
+    fn await_request(
+        &self,
+        stream: &mut impl Connection,
+        buf: &mut Vec<u8>,
+    ) -> bool {
+        let _ = stream.socket().set_read_timeout(self.keep_alive_timeout);
+        let read = read_some(stream, buf, HEADER_READ_BYTES);
+        let _ = stream.socket().set_read_timeout(self.read_timeout);
+        read.is_ok()
+    }
 
-#[cfg(feature = "tls")]
-use rustls::ServerConfig;
+    /// Run `request` through the middleware and then the handler it matches,
+    /// answering with a `500 Internal Server Error` if either panics. The
+    /// request's ID, if it has one, is added to the response.
+    fn dispatch(&self, request: Request) -> Response {
+        let id = request.id().to_string();
+        let response = self.guarded_run(request);
+        if id.is_empty() {
+            response
+        } else {
+            response.header("X-Request-Id", id)
+        }
+    }
 
-#[cfg(feature = "tls")]
-use std::{fs::File, io::BufReader, path::Path};
+    /// Give `request` the ID the client sent or a new one, when enabled
+    fn assign_id(&self, request: &mut Request) {
+        if !self.request_ids {
+            return;
+        }
+        let id = request
+            .header("x-request-id")
+            .map(str::trim)
+            .filter(|id| {
+                !id.is_empty()
+                    && id.len() <= 128
+                    && id.bytes().all(|byte| byte.is_ascii_graphic())
+            })
+            .map_or_else(request_id, str::to_string);
+        request.set_id(id);
+    }
 
-#[cfg(feature = "tls")]
-pub struct TlsServer;
+    /// `run`, answering panics with the error handler
+    fn guarded_run(&self, request: Request) -> Response {
+        self.catch_panic(|| self.run(request)).unwrap_or_else(|| {
+            (self.error_handler)(HttpError::new(
+                StatusCode::InternalServerError,
+                "the handler panicked",
+            ))
+        })
+    }
 
-#[cfg(feature = "tls")]
-impl TlsServer {
-    pub fn bind(
-        addr: impl ToSocketAddrs,
-        private_key: impl AsRef<Path>,
-        certs: impl AsRef<Path>,
-    ) -> TlsServerBuilder {
-        let certs = rustls_pemfile::certs(&mut BufReader::new(
-            &mut File::open(certs).unwrap(),
-        ))
-        .collect::<Result<Vec<_>, _>>()
-        .unwrap();
+    /// Run `f`, or if it panics log the message, pass it to the `on_panic`
+    /// hook and return `None`
+    fn catch_panic<T>(&self, f: impl FnOnce() -> T) -> Option<T> {
+        panic::catch_unwind(AssertUnwindSafe(f))
+            .map_err(|payload| {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .copied()
+                    .or_else(|| {
+                        payload.downcast_ref::<String>().map(String::as_str)
+                    })
+                    .unwrap_or("unknown panic");
+                log::error!("handler panicked: {message}");
+                if let Some(on_panic) = self.on_panic {
+                    on_panic(message);
+                }
+            })
+            .ok()
+    }
 
-        let private_key = rustls_pemfile::private_key(&mut BufReader::new(
-            &mut File::open(private_key).unwrap(),
-        ))
-        .unwrap()
-        .unwrap();
+    /// `dispatch` without the guard. The route is matched up front so
+    /// middleware can see it too.
+    fn run(&self, mut request: Request) -> Response {
+        if self.https_redirect && !request.is_secure() {
+            return match request.header("host").and_then(host_name) {
+                Some(host) => Response::redirect(
+                    format!("https://{host}{}", request.request_target()),
+                    true,
+                ),
+                None => status_response(StatusCode::BadRequest),
+            };
+        }
+        if self.method_override && *request.method() == Method::Post {
+            let method = request
+                .header("x-http-method-override")
+                .and_then(|method| Method::try_from(method.trim()).ok())
+                .filter(|method| {
+                    matches!(
+                        method,
+                        Method::Put | Method::Patch | Method::Delete
+                    )
+                });
+            if let Some(method) = method {
+                request.set_method(method);
+            }
+        }
+        if let Some((route, params)) = self.router.find(request.path()) {
+            request.set_route(&route.pattern);
+            request.set_params(params);
+        }
+        let endpoint = |request| self.call_handler(request);
+        let next = Next::new(&self.middleware, &endpoint);
+        match &self.cors {
+            Some(cors) => cors.handle(request, next),
+            None => next.run(request),
+        }
+    }
 
-        TlsServerBuilder {
-            listener: TcpListener::bind(addr).unwrap(),
-            tls_config: ServerConfig::builder()
-                .with_no_client_auth()
-                .with_single_cert(certs, private_key)
-                .unwrap(),
-            paths: HashMap::new(),
+    /// Run the handler matching `request`
+    fn call_handler(&self, mut request: Request) -> Response {
+        let options = self.auto_options && *request.method() == Method::Options;
+        if options && request.path() == "*" {
+            let methods = self
+                .router
+                .routes()
+                .flat_map(|route| route.methods(self.auto_head));
+            return Response::no_content().header("Allow", self.allow(methods));
         }
+
+        match self.router.find(request.path()) {
+            Some((route, params)) => {
+                match route.handler(request.method(), self.auto_head) {
+                    Some(handler) => {
+                        request.set_route(&route.pattern);
+                        request.set_params(params);
+                        handler.call(request)
+                    }
+                    None if options => Ok(Response::no_content().header(
+                        "Allow",
+                        self.allow(route.methods(self.auto_head)),
+                    )),
+                    None => Ok(status_response(StatusCode::MethodNotAllowed)
+                        .header(
+                            "Allow",
+                            self.allow(route.methods(self.auto_head)),
+                        )),
+                }
+            }
+            None => self.router.default.call(request),
+        }
+        .unwrap_or_else(self.error_handler)
+    }
+
+    /// The value of the `Allow` header for `methods`, adding `OPTIONS` when
+    /// it is answered automatically
+    fn allow(&self, methods: impl Iterator<Item = Method>) -> String {
+        router::allow(
+            methods.chain(self.auto_options.then_some(Method::Options)),
+        )
     }
 }
 
-#[cfg(feature = "tls")]
-pub struct TlsServerBuilder {
-    listener: TcpListener,
-    tls_config: ServerConfig,
-    paths: HashMap<String, Handler>,
+/// Registers handlers sharing one state, see [`ServerBuilder::with_state`]
+pub struct StateBuilder<S> {
+    builder: ServerBuilder,
+    state: Arc<S>,
 }
 
-#[cfg(feature = "tls")]
-impl TlsServerBuilder {
-    pub fn path(mut self, path: &str, handler: Handler) -> Self {
-        self.paths
-            .insert(path.trim_end_matches('/').into(), handler);
+impl<S: Send + Sync + 'static> StateBuilder<S> {
+    /// Like [`ServerBuilder::path`] for a handler using the state
+    pub fn path(
+        mut self,
+        path: &str,
+        handler: impl Fn(Arc<S>, Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        let endpoint = self.bind(handler);
+        self.builder.router.route_mut(path).any = Some(endpoint);
         self
     }
 
-    pub fn listen(self) {
-        let tls_config = Arc::new(self.tls_config);
-        for stream in self.listener.incoming() {
-            match stream {
-                Ok(stream) => {
-                    let tls_config_clone = tls_config.clone();
-                    thread::spawn(move || {
-                        Self::handle_tls(stream, tls_config_clone)
-                    });
-                }
-                Err(err) => println!("{err:?}"),
-            };
-        }
+    /// Like [`ServerBuilder::route`] for a handler using the state
+    pub fn route(
+        mut self,
+        method: Method,
+        path: &str,
+        handler: impl Fn(Arc<S>, Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        let endpoint = self.bind(handler);
+        self.builder
+            .router
+            .route_mut(path)
+            .methods
+            .insert(method, endpoint);
+        self
+    }
+
+    pub fn get(
+        self,
+        path: &str,
+        handler: impl Fn(Arc<S>, Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route(Method::Get, path, handler)
     }
 
-    fn handle_tls(mut stream: TcpStream, tls_config: Arc<ServerConfig>) {
-        println!("{stream:?}");
-        set_stream_timeouts(&stream, Duration::from_millis(1000));
+    pub fn post(
+        self,
+        path: &str,
+        handler: impl Fn(Arc<S>, Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route(Method::Post, path, handler)
+    }
 
-        let mut conn = rustls::ServerConnection::new(tls_config).unwrap();
-        conn.complete_io(&mut stream).unwrap();
+    pub fn put(
+        self,
+        path: &str,
+        handler: impl Fn(Arc<S>, Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route(Method::Put, path, handler)
+    }
 
-        conn.read_tls(&mut stream).unwrap();
-        conn.process_new_packets().unwrap();
-        let mut recv_buf = [0u8; u16::MAX as usize];
-        let _ = conn.reader().read(&mut recv_buf).unwrap();
+    pub fn patch(
+        self,
+        path: &str,
+        handler: impl Fn(Arc<S>, Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route(Method::Patch, path, handler)
+    }
 
-        conn.writer()
-            .write_all("HTTP/1.1 200 OK\r\n\r\n".as_bytes())
-            .unwrap();
-        conn.write_tls(&mut stream).unwrap();
-        conn.process_new_packets().unwrap();
+    pub fn delete(
+        self,
+        path: &str,
+        handler: impl Fn(Arc<S>, Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route(Method::Delete, path, handler)
+    }
+
+    /// Go back to configuring the server
+    pub fn finish(self) -> ServerBuilder {
+        self.builder
+    }
+
+    fn bind(
+        &self,
+        handler: impl Fn(Arc<S>, Request) -> Response + Send + Sync + 'static,
+    ) -> Endpoint {
+        let state = self.state.clone();
+        Endpoint::Handler(Arc::new(move |request| {
+            handler(state.clone(), request)
+        }))
+    }
+}
+
+/// Registers handlers under a common prefix, see [`ServerBuilder::group`]
+pub struct GroupBuilder {
+    builder: ServerBuilder,
+    prefix: String,
+}
+
+impl GroupBuilder {
+    /// Like [`ServerBuilder::path`] with the path under the group's prefix
+    pub fn path(
+        mut self,
+        path: &str,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        let path = self.prefixed(path);
+        self.builder = self.builder.path(&path, handler);
+        self
+    }
+
+    /// Like [`ServerBuilder::route`] with the path under the group's prefix
+    pub fn route(
+        mut self,
+        method: Method,
+        path: &str,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        let path = self.prefixed(path);
+        self.builder = self.builder.route(method, &path, handler);
+        self
+    }
+
+    /// Like [`ServerBuilder::try_path`] with the path under the group's prefix
+    pub fn try_path(
+        mut self,
+        path: &str,
+        handler: impl Fn(Request) -> Result<Response, HttpError>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        let path = self.prefixed(path);
+        self.builder = self.builder.try_path(&path, handler);
+        self
+    }
+
+    /// Like [`ServerBuilder::try_route`] with the path under the group's
+    /// prefix
+    pub fn try_route(
+        mut self,
+        method: Method,
+        path: &str,
+        handler: impl Fn(Request) -> Result<Response, HttpError>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        let path = self.prefixed(path);
+        self.builder = self.builder.try_route(method, &path, handler);
+        self
+    }
+
+    pub fn get(
+        self,
+        path: &str,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route(Method::Get, path, handler)
+    }
+
+    pub fn post(
+        self,
+        path: &str,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route(Method::Post, path, handler)
+    }
+
+    pub fn put(
+        self,
+        path: &str,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route(Method::Put, path, handler)
+    }
+
+    pub fn patch(
+        self,
+        path: &str,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route(Method::Patch, path, handler)
+    }
+
+    pub fn delete(
+        self,
+        path: &str,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route(Method::Delete, path, handler)
+    }
+
+    /// Go back to configuring the server
+    pub fn finish(self) -> ServerBuilder {
+        self.builder
+    }
+
+    fn prefixed(&self, path: &str) -> String {
+        if path.starts_with('/') {
+            format!("{}{path}", self.prefix)
+        } else {
+            format!("{}/{path}", self.prefix)
+        }
+    }
+}
+
+/// A server running on a background thread, see [`ServerBuilder::spawn`]
+pub struct ServerHandle {
+    addr: SocketAddr,
+    running: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl ServerHandle {
+    /// The address the server is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stop accepting connections and wait for the ones already accepted to be
+    /// served, the address is free to bind again once this returns
+    pub fn shutdown(self) {
+        self.running.store(false, Ordering::SeqCst);
+        let _ = self.thread.join();
+    }
+}
+
+/// Why a request could not be read from a stream
+#[derive(Debug)]
+enum ReadError {
+    /// The client closed or reset the connection, there is nobody to
+    /// respond to
+    Disconnected,
+    /// The client stopped sending before the request was complete, the
+    /// connection is closed without a response
+    TimedOut,
+    /// The request should be answered with an error and the connection closed
+    Invalid(Error),
+}
+
+impl From<Error> for ReadError {
+    fn from(err: Error) -> Self {
+        Self::Invalid(err)
+    }
+}
+
+/// Read the next request from `stream`, the headers first and then the body
+/// as declared by `Content-Length` or `Transfer-Encoding: chunked`. Reading
+/// continues from the bytes already in `buf`, and any read past the end of
+/// the request are left there for the next one.
+fn read_request(
+    stream: &mut (impl Read + Write),
+    buf: &mut Vec<u8>,
+    max_header_bytes: usize,
+    max_body: impl FnOnce(&Request) -> usize,
+) -> Result<Request, ReadError> {
+    // The headers can arrive over any number of reads, only the new bytes
+    // and the three before them need searching for the end
+    let mut searched = 0;
+    let header_len = loop {
+        if let Some(position) = buf[searched..]
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+        {
+            break searched + position + 4;
+        }
+        if buf.len() >= max_header_bytes {
+            return Err(Error::HeadersTooLarge.into());
+        }
+        searched = buf.len().saturating_sub(3);
+        read_some(stream, buf, HEADER_READ_BYTES)?;
+    };
+    if header_len > max_header_bytes {
+        return Err(Error::HeadersTooLarge.into());
+    }
+
+    let mut request = Request::from_bytes(&buf[..header_len])?;
+    let max_body = max_body(&request);
+
+    if request.chunked() {
+        continue_body(stream, &request, buf.len() > header_len)?;
+        let mut decoder = ChunkedDecoder::new(max_body);
+        while !decoder.decode(&buf[header_len..])? {
+            read_some(stream, buf, CHUNKED_READ_BYTES)?;
+        }
+        buf.drain(..header_len + decoder.consumed());
+        *request.body_mut() = decoder.into_body();
+        return Ok(request);
+    }
+
+    let content_len = request.content_len();
+    if content_len > max_body {
+        return Err(Error::PayloadTooLarge.into());
+    }
+
+    if content_len > 0 {
+        continue_body(stream, &request, buf.len() > header_len)?;
+    }
+    let request_len = header_len + content_len;
+    while buf.len() < request_len {
+        let remaining = request_len - buf.len();
+        read_some(stream, buf, remaining)?;
+    }
+    *request.body_mut() = buf.drain(..request_len).skip(header_len).collect();
+
+    Ok(request)
+}
+
+/// Tell a client waiting with `Expect: 100-continue` to send the body, unless
+/// some of it has already been `buffered`
+fn continue_body(
+    stream: &mut impl Write,
+    request: &Request,
+    buffered: bool,
+) -> Result<(), ReadError> {
+    let expects_continue = request
+        .header("expect")
+        .is_some_and(|value| value.trim().eq_ignore_ascii_case("100-continue"));
+    if !expects_continue || buffered || *request.protocol() != Protocol::Http1_1
+    {
+        return Ok(());
+    }
+    stream
+        .write_all(
+            format!("HTTP/1.1 {}\r\n\r\n", StatusCode::Continue).as_bytes(),
+        )
+        .and_then(|()| stream.flush())
+        .map_err(|_| ReadError::Disconnected)
+}
+
+/// A connection requests are answered on, plain or TLS
+trait Connection: Read + Write {
+    /// The TCP socket underneath, for changing its timeouts
+    fn socket(&self) -> &TcpStream;
+
+    /// Whether the connection is encrypted with TLS
+    fn secure(&self) -> bool {
+        false
+    }
+}
+
+impl Connection for TcpStream {
+    fn socket(&self) -> &TcpStream {
+        self
+    }
+}
+
+#[cfg(feature = "tls")]
+impl Connection for rustls::StreamOwned<rustls::ServerConnection, TcpStream> {
+    fn socket(&self) -> &TcpStream {
+        &self.sock
+    }
+
+    fn secure(&self) -> bool {
+        true
+    }
+}
+
+/// A connection that reads the bytes in `buffered` before any more from
+/// `stream`, for handing on a connection with some of it already read
+struct Replay<'a, S> {
+    buffered: Vec<u8>,
+    read: usize,
+    stream: &'a mut S,
+}
+
+impl<S: Read> Read for Replay<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let buffered = &self.buffered[self.read..];
+        if buffered.is_empty() {
+            return self.stream.read(buf);
+        }
+        let len = buffered.len().min(buf.len());
+        buf[..len].copy_from_slice(&buffered[..len]);
+        self.read += len;
+        Ok(len)
+    }
+}
+
+impl<S: Write> Write for Replay<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Append at least one and at most `max` bytes from `stream` to `buf`
+fn read_some(
+    stream: &mut impl Read,
+    buf: &mut Vec<u8>,
+    max: usize,
+) -> Result<(), ReadError> {
+    let len = buf.len();
+    buf.resize(len + max, 0);
+
+    let read = stream.read(&mut buf[len..]);
+    buf.truncate(len + *read.as_ref().unwrap_or(&0));
+    match read {
+        Ok(0) => Err(ReadError::Disconnected),
+        Ok(_) => Ok(()),
+        Err(e)
+            if matches!(
+                e.kind(),
+                std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock
+            ) =>
+        {
+            Err(ReadError::TimedOut)
+        }
+        // Anything else, such as a reset, a failed TLS handshake or a TLS
+        // client closing without a close_notify, leaves nobody to answer
+        Err(e) => {
+            log::debug!("connection closed: {e}");
+            Err(ReadError::Disconnected)
+        }
+    }
+}
+
+/// Hand each connection from `incoming` to `accept` until it ends or fails
+/// with an error that isn't transient, which is returned. Transient errors
+/// are retried with a pause that grows while they keep coming, so one that
+/// persists doesn't spin the thread. Each error is passed to `on_error`.
+fn accept_loop(
+    incoming: impl Iterator<Item = std::io::Result<TcpStream>>,
+    on_error: Option<fn(&std::io::Error)>,
+    mut accept: impl FnMut(TcpStream),
+) -> std::io::Result<()> {
+    let mut backoff = ACCEPT_BACKOFF;
+    for stream in incoming {
+        match stream {
+            Ok(stream) => {
+                backoff = ACCEPT_BACKOFF;
+                accept(stream);
+            }
+            Err(err) if transient_accept_error(&err) => {
+                log::warn!("failed to accept connection: {err}");
+                if let Some(on_error) = on_error {
+                    on_error(&err);
+                }
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+            }
+            Err(err) => {
+                log::error!("stopped accepting connections: {err}");
+                if let Some(on_error) = on_error {
+                    on_error(&err);
+                }
+                return Err(err);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A new request ID, a random part so they differ between runs and processes
+/// and a count so they never repeat within one
+fn request_id() -> String {
+    use std::{
+        collections::hash_map::RandomState,
+        hash::BuildHasher,
+        sync::{atomic::AtomicU64, OnceLock},
+    };
+
+    static COUNT: AtomicU64 = AtomicU64::new(0);
+    static KEYS: OnceLock<RandomState> = OnceLock::new();
+    let count = COUNT.fetch_add(1, Ordering::Relaxed);
+    let random = KEYS.get_or_init(RandomState::new).hash_one(count);
+    format!("{random:016x}-{count:x}")
+}
+
+/// Run [`accept_loop`] for each of `listeners` on threads of their own, all
+/// but the first, until they have all stopped, returning the first error
+fn accept_all(
+    listeners: &[TcpListener],
+    on_error: Option<fn(&std::io::Error)>,
+    accept: impl Fn(TcpStream) + Sync,
+) -> std::io::Result<()> {
+    let accept = &accept;
+    thread::scope(|scope| {
+        let others = listeners[1..]
+            .iter()
+            .map(|listener| {
+                scope.spawn(move || {
+                    accept_loop(listener.incoming(), on_error, accept)
+                })
+            })
+            .collect::<Vec<_>>();
+        let first = accept_loop(listeners[0].incoming(), on_error, accept);
+        others
+            .into_iter()
+            .map(|other| other.join().expect("accept loops don't panic"))
+            .fold(first, Result::and)
+    })
+}
+
+/// Whether accepting can be tried again after `err`, the client having gone
+/// before it was accepted or the process being short of resources for now
+fn transient_accept_error(err: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+
+    // ENOMEM, ENFILE and EMFILE, running out of memory or file descriptors
+    #[cfg(unix)]
+    if matches!(err.raw_os_error(), Some(12 | 23 | 24)) {
+        return true;
+    }
+    matches!(
+        err.kind(),
+        ErrorKind::ConnectionAborted
+            | ErrorKind::ConnectionReset
+            | ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::TimedOut
+            | ErrorKind::OutOfMemory
+            | ErrorKind::NetworkDown
+            | ErrorKind::NetworkUnreachable
+            | ErrorKind::HostUnreachable
+    )
+}
+
+/// Marks a connection as open, see [`ServerBuilder::max_connections`]
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Answer a connection there is no room for with a `503 Service Unavailable`
+fn refuse(mut stream: TcpStream, write_timeout: Option<Duration>) {
+    let _ = stream.set_write_timeout(write_timeout);
+    let _ = status_response(StatusCode::ServiceUnavailable)
+        .header("Connection", "close")
+        .write_to(&mut stream, true)
+        .and_then(|_| stream.flush());
+    let _ = stream.shutdown(Shutdown::Write);
+}
+
+fn set_stream_timeouts(
+    stream: &TcpStream,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+) {
+    stream.set_read_timeout(read_timeout).unwrap();
+    stream.set_write_timeout(write_timeout).unwrap();
+}
+
+/// The `Host` header `host` without its port, `None` if it is empty
+fn host_name(host: &str) -> Option<&str> {
+    let host = host.trim();
+    // The port follows the last `:`, unless that is inside an IPv6 address
+    let name = match host.rsplit_once(':') {
+        Some((name, port))
+            if !port.contains(']')
+                && port.bytes().all(|byte| byte.is_ascii_digit()) =>
+        {
+            name
+        }
+        _ => host,
+    };
+    (!name.is_empty()).then_some(name)
+}
+
+/// A plain response for `status_code` with its reason as the body
+fn status_response(status_code: StatusCode) -> Response {
+    Response::new().status(status_code).text_body(status_code)
+}
+
+fn error_response(err: HttpError) -> Response {
+    Response::new().status(err.status_code()).body(format!(
+        "{}\n{}",
+        err.status_code(),
+        err.message()
+    ))
+}
+
+pub(crate) fn not_found(_: Request) -> Response {
+    Response::new()
+        .status(http::StatusCode::NotFound)
+        .body("404 Not Found\nOops! Looks like Nessie took our page for a swim in the Loch")
+}
+
+#[cfg(feature = "tls")]
+use rustls::ServerConfig;
+
+#[cfg(feature = "tls")]
+use std::{fs, path::Path};
+
+/// How long to wait on a TLS connection unless configured otherwise
+#[cfg(feature = "tls")]
+const DEFAULT_TLS_TIMEOUT: Duration = Duration::from_millis(1000);
+
+#[cfg(feature = "tls")]
+pub struct TlsServer;
+
+#[cfg(feature = "tls")]
+impl TlsServer {
+    pub fn bind(
+        addr: impl ToSocketAddrs,
+        private_key: impl AsRef<Path>,
+        certs: impl AsRef<Path>,
+    ) -> Result<TlsServerBuilder, BindError> {
+        let read = |path: &Path| {
+            fs::read(path)
+                .map_err(|err| format!("reading {}: {err}", path.display()))
+        };
+        let private_key =
+            read(private_key.as_ref()).map_err(BindError::InvalidKey)?;
+        let certs = read(certs.as_ref()).map_err(BindError::InvalidCert)?;
+        Self::bind_pem(addr, &private_key, &certs)
+    }
+
+    /// Like [`TlsServer::bind`] with the PEM encoded private key and
+    /// certificate chain already in memory, such as from a secret manager
+    pub fn bind_pem(
+        addr: impl ToSocketAddrs,
+        private_key: &[u8],
+        certs: &[u8],
+    ) -> Result<TlsServerBuilder, BindError> {
+        let certs = rustls_pemfile::certs(&mut &*certs)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| BindError::InvalidCert(err.to_string()))?;
+        if certs.is_empty() {
+            return Err(BindError::InvalidCert("no certificates found".into()));
+        }
+
+        let private_key = rustls_pemfile::private_key(&mut &*private_key)
+            .map_err(|err| BindError::InvalidKey(err.to_string()))?
+            .ok_or_else(|| {
+                BindError::InvalidKey("no private key found".into())
+            })?;
+
+        let tls_config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certs, private_key)
+            .map_err(|err| BindError::InvalidKey(err.to_string()))?;
+
+        Ok(TlsServerBuilder {
+            builder: ServerBuilder::new(vec![TcpListener::bind(addr)?])
+                .read_timeout(DEFAULT_TLS_TIMEOUT)
+                .write_timeout(DEFAULT_TLS_TIMEOUT),
+            tls_config: Arc::new(tls_config),
+        })
+    }
+}
+
+/// Serves requests like [`ServerBuilder`] over TLS, requests are routed and
+/// answered the same way
+#[cfg(feature = "tls")]
+pub struct TlsServerBuilder {
+    builder: ServerBuilder,
+    tls_config: Arc<ServerConfig>,
+}
+
+#[cfg(feature = "tls")]
+impl TlsServerBuilder {
+    /// Like [`ServerBuilder::path`]
+    pub fn path(
+        mut self,
+        path: &str,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.builder = self.builder.path(path, handler);
+        self
+    }
+
+    /// Like [`ServerBuilder::route`]
+    pub fn route(
+        mut self,
+        method: Method,
+        path: &str,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.builder = self.builder.route(method, path, handler);
+        self
+    }
+
+    pub fn get(
+        self,
+        path: &str,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route(Method::Get, path, handler)
+    }
+
+    pub fn post(
+        self,
+        path: &str,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route(Method::Post, path, handler)
+    }
+
+    pub fn put(
+        self,
+        path: &str,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route(Method::Put, path, handler)
+    }
+
+    pub fn patch(
+        self,
+        path: &str,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route(Method::Patch, path, handler)
+    }
+
+    pub fn delete(
+        self,
+        path: &str,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route(Method::Delete, path, handler)
+    }
+
+    /// Like [`ServerBuilder::default`]
+    pub fn default(
+        mut self,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.builder = self.builder.default(handler);
+        self
+    }
+
+    /// Like [`ServerBuilder::max_body`]
+    pub fn max_body(mut self, bytes: usize) -> Self {
+        self.builder = self.builder.max_body(bytes);
+        self
+    }
+
+    /// Like [`ServerBuilder::max_header_bytes`]
+    pub fn max_header_bytes(mut self, bytes: usize) -> Self {
+        self.builder = self.builder.max_header_bytes(bytes);
+        self
+    }
+
+    /// Like [`ServerBuilder::workers`]
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.builder = self.builder.workers(workers);
+        self
+    }
+
+    /// Like [`ServerBuilder::max_connections`], except connections over the
+    /// limit are closed without a response
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.builder = self.builder.max_connections(max);
+        self
+    }
+
+    /// Like [`ServerBuilder::middleware`]
+    pub fn middleware(
+        mut self,
+        middleware: impl Fn(Request, Next) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.builder = self.builder.middleware(middleware);
+        self
+    }
+
+    /// Like [`ServerBuilder::read_timeout`], defaults to one second
+    pub fn read_timeout(
+        mut self,
+        timeout: impl Into<Option<Duration>>,
+    ) -> Self {
+        self.builder = self.builder.read_timeout(timeout);
+        self
+    }
+
+    /// Like [`ServerBuilder::write_timeout`], defaults to one second
+    pub fn write_timeout(
+        mut self,
+        timeout: impl Into<Option<Duration>>,
+    ) -> Self {
+        self.builder = self.builder.write_timeout(timeout);
+        self
+    }
+
+    /// Like [`ServerBuilder::keep_alive_timeout`]
+    pub fn keep_alive_timeout(
+        mut self,
+        timeout: impl Into<Option<Duration>>,
+    ) -> Self {
+        self.builder = self.builder.keep_alive_timeout(timeout);
+        self
+    }
+
+    /// Like [`ServerBuilder::max_requests_per_connection`]
+    pub fn max_requests_per_connection(mut self, requests: usize) -> Self {
+        self.builder = self.builder.max_requests_per_connection(requests);
+        self
+    }
+
+    /// Like [`ServerBuilder::on_accept_error`]
+    pub fn on_accept_error(mut self, hook: fn(&std::io::Error)) -> Self {
+        self.builder = self.builder.on_accept_error(hook);
+        self
+    }
+
+    /// Like [`ServerBuilder::listen`]
+    pub fn listen(self) -> std::io::Result<()> {
+        let server = Arc::new(self);
+        let pool = ThreadPool::new(server.builder.workers);
+
+        let on_error = server.builder.on_accept_error;
+        accept_all(&server.builder.listeners, on_error, |stream| {
+            // Refusing with a 503 would need a TLS handshake first
+            let Some(slot) = server.builder.open_connection() else {
+                log::warn!("too many connections, refusing one");
+                return;
+            };
+            let server = server.clone();
+            pool.execute(move || {
+                server.handle_tls(stream);
+                drop(slot);
+            });
+        })
+    }
+
+    fn handle_tls(&self, stream: TcpStream) {
+        set_stream_timeouts(
+            &stream,
+            self.builder.read_timeout,
+            self.builder.write_timeout,
+        );
+
+        let conn = match rustls::ServerConnection::new(self.tls_config.clone())
+        {
+            Ok(conn) => conn,
+            Err(err) => {
+                log::warn!("failed to start TLS connection: {err}");
+                return;
+            }
+        };
+        let peer_addr = stream.peer_addr().ok();
+        let mut stream = rustls::StreamOwned::new(conn, stream);
+        self.builder.serve(&mut stream, peer_addr);
+
+        stream.conn.send_close_notify();
+        let _ = stream.flush();
+        let _ = stream.sock.shutdown(Shutdown::Write);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Write, sync::atomic::AtomicUsize};
+
+    /// Run `builder` on a background thread, returning the address it is
+    /// listening on
+    fn serve(builder: ServerBuilder) -> SocketAddr {
+        let addr = builder.local_addrs()[0];
+        thread::spawn(move || builder.listen());
+        addr
+    }
+
+    /// Send a raw request on a new connection and read the response
+    fn send(addr: SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        read_response(&mut stream)
+    }
+
+    /// Read a single response using its `Content-Length`
+    fn read_response(stream: &mut TcpStream) -> String {
+        let mut response = Vec::new();
+        let mut byte = [0u8];
+        while !response.ends_with(b"\r\n\r\n") {
+            if stream.read(&mut byte).unwrap() == 0 {
+                break;
+            }
+            response.push(byte[0]);
+        }
+
+        let content_len = String::from_utf8_lossy(&response)
+            .lines()
+            .find_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                key.eq_ignore_ascii_case("content-length")
+                    .then(|| value.trim().parse().unwrap())
+            })
+            .unwrap_or(0);
+        let mut body = vec![0; content_len];
+        stream.read_exact(&mut body).unwrap();
+        response.extend(body);
+
+        String::from_utf8(response).unwrap()
+    }
+
+    /// Whether the server has closed `stream`
+    fn closed(stream: &mut TcpStream) -> bool {
+        matches!(stream.read(&mut [0u8]), Ok(0))
+    }
+
+    fn get_users(_: Request) -> Response {
+        Response::new().body("list users")
+    }
+
+    fn post_users(_: Request) -> Response {
+        Response::new().body("create user")
+    }
+
+    #[test]
+    fn route_by_method() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .get("/users", get_users)
+                .post("/users", post_users),
+        );
+
+        let response = send(addr, "GET /users HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("list users"));
+
+        let response =
+            send(addr, "POST /users HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+        assert!(response.ends_with("create user"));
+    }
+
+    #[test]
+    fn method_route_overrides_path() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .path("/users", get_users)
+                .post("/users", post_users),
+        );
+
+        let response = send(addr, "PUT /users HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("list users"));
+
+        let response = send(addr, "POST /users HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("create user"));
+    }
+
+    #[test]
+    fn malformed_request_is_bad_request() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .get("/users", get_users),
+        );
+
+        let response = send(addr, "GET /users\r\nHost localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn root_path() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .path("/", |request| Response::new().body(request.path())),
+        );
+
+        let response = send(addr, "GET / HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\n/"));
+
+        let response = send(addr, "GET /anything HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn normalised_paths() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .get("/users", get_users)
+                .get("/users/:id", get_user),
+        );
+
+        let response = send(addr, "GET //users// HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("list users"));
+
+        let response = send(addr, "GET /groups/../users/./7 HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("user 7"));
+
+        let response = send(addr, "GET /../users HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn unsupported_protocol() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .get("/users", get_users),
+        );
+
+        for request in ["GET / HTTP/3.0\r\n\r\n", "GET / SPDY\r\n\r\n"] {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            let response = read_response(&mut stream);
+            assert!(response
+                .starts_with("HTTP/1.1 505 HTTP Version Not Supported\r\n"));
+            assert!(closed(&mut stream));
+        }
+    }
+
+    fn body_len(request: Request) -> Response {
+        Response::new().text_body(request.body_bytes().len())
+    }
+
+    #[test]
+    fn read_large_body() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .post("/upload", body_len),
+        );
+
+        let body = "a".repeat(100 * 1024);
+        let request = format!(
+            "POST /upload HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let response = send(addr, &request);
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with(&format!("\r\n\r\n{}", 100 * 1024)));
+    }
+
+    #[test]
+    fn read_body_larger_than_64k() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .post("/upload", body_len),
+        );
+
+        let body = "a".repeat(1024 * 1024);
+        let request = format!(
+            "POST /upload HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let response = send(addr, &request);
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with(&format!("\r\n\r\n{}", 1024 * 1024)));
+    }
+
+    #[test]
+    fn body_too_large() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .max_body(1000)
+                .post("/upload", body_len),
+        );
+
+        let request = "POST /upload HTTP/1.1\r\nContent-Length: 1001\r\n\r\n";
+        let response = send(addr, request);
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+    }
+
+    static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+    static MAX_ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+    fn slow(_: Request) -> Response {
+        let active = ACTIVE.fetch_add(1, Ordering::SeqCst) + 1;
+        MAX_ACTIVE.fetch_max(active, Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(50));
+        ACTIVE.fetch_sub(1, Ordering::SeqCst);
+        Response::new()
+    }
+
+    #[test]
+    fn workers_bound_concurrency() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .workers(2)
+                .get("/", slow),
+        );
+
+        let clients: Vec<_> = (0..6)
+            .map(|_| {
+                thread::spawn(move || send(addr, "GET / HTTP/1.1\r\n\r\n"))
+            })
+            .collect();
+        for client in clients {
+            assert!(client.join().unwrap().starts_with("HTTP/1.1 200"));
+        }
+
+        assert!(MAX_ACTIVE.load(Ordering::SeqCst) <= 2);
+    }
+
+    #[test]
+    fn route_ignores_query() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .get("/users", get_users),
+        );
+
+        let response = send(addr, "GET /users?page=2 HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("list users"));
+    }
+
+    #[test]
+    fn route_decoded_path() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .get("/hello world", get_users),
+        );
+
+        let response = send(addr, "GET /hello%20world HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("list users"));
+
+        let response = send(addr, "GET /hello% HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 400"));
+    }
+
+    fn get_user(request: Request) -> Response {
+        Response::new().body(format!("user {}", request.param("id").unwrap()))
+    }
+
+    #[test]
+    fn route_path_param() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .get("/users/:id", get_user)
+                .get("/users/me/", get_users),
+        );
+
+        let response = send(addr, "GET /users/42/ HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("user 42"));
+
+        let response = send(addr, "GET /users/me HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("list users"));
+    }
+
+    fn get_file(request: Request) -> Response {
+        Response::new().body(request.param("path").unwrap())
+    }
+
+    #[test]
+    fn route_wildcard() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .get("/static/*path", get_file),
+        );
+
+        let response = send(addr, "GET /static/a/b/c.png HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\na/b/c.png"));
+    }
+
+    fn user_agent(request: Request) -> Response {
+        Response::new().body(request.header("User-Agent").unwrap_or("none"))
+    }
+
+    #[test]
+    fn handler_reads_header() {
+        let addr =
+            serve(Server::bind("127.0.0.1:0").unwrap().get("/", user_agent));
+
+        let response =
+            send(addr, "GET / HTTP/1.1\r\nuser-agent: curl/8.0\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\ncurl/8.0"));
+    }
+
+    #[test]
+    fn keep_alive() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .get("/users", get_users)
+                .post("/users", post_users),
+        );
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        stream.write_all(b"GET /users HTTP/1.1\r\n\r\n").unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.contains("\r\nConnection: keep-alive\r\n"));
+        assert!(response.ends_with("list users"));
+
+        stream.write_all(b"POST /users HTTP/1.1\r\n\r\n").unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.ends_with("create user"));
+
+        stream
+            .write_all(b"GET /users HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.contains("\r\nConnection: close\r\n"));
+        assert!(closed(&mut stream));
+    }
+
+    #[test]
+    fn http_1_0_closes() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .get("/users", get_users),
+        );
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /users HTTP/1.0\r\n\r\n").unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(response.contains("\r\nConnection: close\r\n"));
+        assert!(closed(&mut stream));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /users HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+            .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(response.contains("\r\nConnection: keep-alive\r\n"));
+    }
+
+    #[test]
+    fn client_close() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .get("/users", get_users),
+        );
+
+        for protocol in ["HTTP/1.1", "HTTP/1.0"] {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(
+                stream,
+                "GET /users {protocol}\r\nConnection: keep-alive, close\r\n\r\n"
+            )
+            .unwrap();
+            let response = read_response(&mut stream);
+            assert!(response.contains("\r\nConnection: close\r\n"));
+            assert!(response.ends_with("list users"));
+            assert!(closed(&mut stream));
+        }
+    }
+
+    #[test]
+    fn max_requests_per_connection() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .max_requests_per_connection(2)
+                .get("/users", get_users),
+        );
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        stream.write_all(b"GET /users HTTP/1.1\r\n\r\n").unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.contains("\r\nConnection: keep-alive\r\n"));
+
+        stream.write_all(b"GET /users HTTP/1.1\r\n\r\n").unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.contains("\r\nConnection: close\r\n"));
+        assert!(response.ends_with("list users"));
+        assert!(closed(&mut stream));
+    }
+
+    #[test]
+    fn keep_alive_timeout() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .keep_alive_timeout(Duration::from_millis(100))
+                .get("/users", get_users),
+        );
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        stream.write_all(b"GET /users HTTP/1.1\r\n\r\n").unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.contains("\r\nConnection: keep-alive\r\n"));
+
+        let start = std::time::Instant::now();
+        assert!(closed(&mut stream));
+        assert!(start.elapsed() < DEFAULT_TIMEOUT);
+    }
+
+    #[test]
+    fn http_1_0_stream() {
+        let addr = serve(Server::bind("127.0.0.1:0").unwrap().get("/", |_| {
+            Response::stream(["hello", " world"].map(Into::into).into_iter())
+        }));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(response.contains("\r\nConnection: close\r\n"));
+        assert!(!response.contains("Transfer-Encoding"));
+        assert!(response.ends_with("\r\n\r\nhello world"));
+    }
+
+    #[test]
+    fn slow_body_within_timeout() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .read_timeout(Duration::from_millis(500))
+                .post("/upload", body_len),
+        );
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"POST /upload HTTP/1.1\r\nContent-Length: 4\r\n\r\nab")
+            .unwrap();
+        thread::sleep(Duration::from_millis(200));
+        stream.write_all(b"cd").unwrap();
+
+        let response = read_response(&mut stream);
+        assert!(response.ends_with("\r\n\r\n4"));
+    }
+
+    #[test]
+    fn slow_body_past_timeout() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .read_timeout(Duration::from_millis(100))
+                .write_timeout(None)
+                .post("/upload", body_len),
+        );
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"POST /upload HTTP/1.1\r\nContent-Length: 4\r\n\r\nab")
+            .unwrap();
+        thread::sleep(Duration::from_millis(300));
+        assert!(closed(&mut stream));
+    }
+
+    static REQUESTS: AtomicUsize = AtomicUsize::new(0);
+    static ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+    #[test]
+    fn hooks_fire() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .on_request(|_| {
+                    REQUESTS.fetch_add(1, Ordering::SeqCst);
+                })
+                .on_error(|err| {
+                    assert_eq!(*err, Error::InvalidMethod);
+                    ERRORS.fetch_add(1, Ordering::SeqCst);
+                })
+                .get("/users", get_users),
+        );
+
+        send(addr, "GET /users HTTP/1.1\r\n\r\n");
+        send(addr, "GET /groups HTTP/1.1\r\n\r\n");
+        send(addr, "BREW /users HTTP/1.1\r\n\r\n");
+
+        assert_eq!(REQUESTS.load(Ordering::SeqCst), 2);
+        assert_eq!(ERRORS.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn shutdown_releases_port() {
+        let server = Server::bind("127.0.0.1:0")
+            .unwrap()
+            .get("/users", get_users)
+            .spawn()
+            .unwrap();
+        let addr = server.local_addr();
+
+        let response = send(addr, "GET /users HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("list users"));
+
+        server.shutdown();
+        assert!(TcpStream::connect(addr).is_err());
+        TcpListener::bind(addr).unwrap();
+    }
+
+    #[test]
+    fn shutdown_waits_for_in_flight() {
+        let server = Server::bind("127.0.0.1:0")
+            .unwrap()
+            .get("/slow", |_| {
+                thread::sleep(Duration::from_millis(200));
+                Response::new().body("done")
+            })
+            .spawn()
+            .unwrap();
+        let addr = server.local_addr();
+
+        let client = thread::spawn(move || {
+            send(addr, "GET /slow HTTP/1.1\r\nConnection: close\r\n\r\n")
+        });
+        thread::sleep(Duration::from_millis(50));
+        server.shutdown();
+
+        assert!(client.join().unwrap().ends_with("done"));
+    }
+
+    fn reject(_: Request) -> Result<Response, HttpError> {
+        Err(HttpError::new(StatusCode::BadRequest, "nope"))
+    }
+
+    fn accept(_: Request) -> Result<Response, HttpError> {
+        Ok(Response::new().body("yep"))
+    }
+
+    #[test]
+    fn try_handler_error() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .try_path("/reject", reject)
+                .try_route(Method::Get, "/accept", accept),
+        );
+
+        let response = send(addr, "GET /reject HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(response.ends_with("\r\n\r\n400 Bad Request\nnope"));
+
+        let response = send(addr, "GET /accept HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("yep"));
+    }
+
+    #[test]
+    fn custom_error_handler() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .error_handler(|err| {
+                    Response::new()
+                        .status(err.status_code())
+                        .body(format!("{{\"error\":\"{}\"}}", err.message()))
+                })
+                .try_path("/reject", reject),
+        );
+
+        let response = send(addr, "GET /reject HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 400"));
+        assert!(response.ends_with("{\"error\":\"nope\"}"));
+    }
+
+    fn count(counter: Arc<AtomicUsize>, _: Request) -> Response {
+        Response::new().text_body(counter.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+
+    #[test]
+    fn shared_state() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .get("/users", get_users)
+                .with_state(AtomicUsize::new(0))
+                .get("/a", count)
+                .post("/b", count)
+                .finish(),
+        );
+
+        let response = send(addr, "GET /a HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\n1"));
+        let response = send(addr, "POST /b HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\n2"));
+        let response = send(addr, "GET /a HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\n3"));
+
+        let response = send(addr, "GET /users HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("list users"));
+    }
+
+    #[test]
+    fn closure_handler() {
+        let prefix = String::from("hello ");
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .get("/users/:name", move |request| {
+                    Response::new().body(format!(
+                        "{prefix}{}",
+                        request.param("name").unwrap()
+                    ))
+                })
+                .default(|_| Response::new().body("nothing here")),
+        );
+
+        let response = send(addr, "GET /users/nessie HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\nhello nessie"));
+
+        let response = send(addr, "GET /groups HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\nnothing here"));
+    }
+
+    #[test]
+    fn middleware_wraps_handlers() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .middleware(|request, next| {
+                    next.run(request).header("X-Trace-Id", "abc123")
+                })
+                .get("/users", get_users),
+        );
+
+        let response = send(addr, "GET /users HTTP/1.1\r\n\r\n");
+        assert!(response.contains("\r\nX-Trace-Id: abc123\r\n"));
+        assert!(response.ends_with("list users"));
+
+        let response = send(addr, "GET /groups HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404"));
+        assert!(response.contains("\r\nX-Trace-Id: abc123\r\n"));
+    }
+
+    #[test]
+    fn middleware_order() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .middleware(|request, next| {
+                    let response = next.run(request);
+                    let order =
+                        response.header_value("X-Order").unwrap().to_string();
+                    response.header("X-Order", format!("{order},outer"))
+                })
+                .middleware(|request, next| {
+                    if request.header("Authorization").is_none() {
+                        return Response::new()
+                            .status(StatusCode::Unauthorized)
+                            .header("X-Order", "inner");
+                    }
+                    next.run(request).header("X-Order", "inner")
+                })
+                .get("/users", get_users),
+        );
+
+        let response = send(addr, "GET /users HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 401"));
+        assert!(response.contains("\r\nX-Order: inner,outer\r\n"));
+
+        let response =
+            send(addr, "GET /users HTTP/1.1\r\nAuthorization: yes\r\n\r\n");
+        assert!(response.ends_with("list users"));
+    }
+
+    fn echo(request: Request) -> Response {
+        Response::new().body(request.body_bytes())
+    }
+
+    #[test]
+    fn read_chunked_body() {
+        let addr =
+            serve(Server::bind("127.0.0.1:0").unwrap().post("/echo", echo));
+
+        let response = send(
+            addr,
+            "POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n7\r\n, world\r\n0\r\n\r\n",
+        );
+        assert!(response.ends_with("\r\n\r\nhello, world"));
+
+        let response = send(
+            addr,
+            "POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nhello\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 400"));
+    }
+
+    #[test]
+    fn unframed_bodies_close_the_connection() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .post("/echo", echo)
+                .get("/secret", |_| Response::new().body("secret")),
+        );
+
+        // Either way the body could hide a second request
+        for framing in [
+            "Transfer-Encoding: gzip\r\n",
+            "Content-Length: 0\r\nTransfer-Encoding: chunked\r\n",
+        ] {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(
+                stream,
+                "POST /echo HTTP/1.1\r\n{framing}\r\n0\r\n\r\nGET /secret HTTP/1.1\r\n\r\n"
+            )
+            .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+            assert!(response.contains("\r\nConnection: close\r\n"));
+            assert!(!response.contains("secret"));
+        }
+    }
+
+    #[test]
+    fn unmatched_path_uses_default() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .get("/users", get_users),
+        );
+
+        let response = send(addr, "GET /groups HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn unmatched_method_not_allowed() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .get("/x", get_users)
+                .post("/y", post_users),
+        );
+
+        let response = send(addr, "POST /x HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(response.contains("\r\nAllow: GET, HEAD, OPTIONS\r\n"));
+    }
+
+    #[test]
+    fn allow_lists_registered_methods() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .post("/users", post_users)
+                .get("/users", get_users),
+        );
+
+        let response = send(addr, "DELETE /users HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405"));
+        assert!(response.contains("\r\nAllow: GET, HEAD, OPTIONS, POST\r\n"));
+    }
+
+    #[test]
+    fn stream_response() {
+        let addr =
+            serve(Server::bind("127.0.0.1:0").unwrap().get("/count", |_| {
+                Response::stream((1..=3).map(|n| n.to_string().into_bytes()))
+            }));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /count HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.contains("\r\nTransfer-Encoding: chunked\r\n"));
+        assert!(response
+            .ends_with("\r\n\r\n1\r\n1\r\n1\r\n2\r\n1\r\n3\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn event_stream() {
+        let addr =
+            serve(Server::bind("127.0.0.1:0").unwrap().get("/events", |_| {
+                Response::event_stream(
+                    [sse::event("greeting", "hello"), sse::event("", "a\nb")]
+                        .into_iter(),
+                )
+            }));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /events HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.contains("\r\nContent-Type: text/event-stream\r\n"));
+        assert!(response.contains("\r\nCache-Control: no-cache\r\n"));
+        assert!(response.ends_with(
+            "\r\n\r\n1d\r\nevent: greeting\ndata: hello\n\n\r\n\
+            11\r\ndata: a\ndata: b\n\n\r\n0\r\n\r\n"
+        ));
+    }
+
+    #[cfg(feature = "gzip")]
+    static GZIPPED: AtomicUsize = AtomicUsize::new(0);
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn compress_response() {
+        use flate2::read::GzDecoder;
+
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .compression(true)
+                .on_response(|_, written| {
+                    GZIPPED.fetch_max(written, Ordering::SeqCst);
+                })
+                .get("/long", |_| {
+                    Response::new()
+                        .header("Vary", "Origin")
+                        .body("a".repeat(4096))
+                })
+                .get("/short", |_| Response::new().body("short")),
+        );
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /long HTTP/1.1\r\nAccept-Encoding: gzip\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let split = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .unwrap();
+        let head = String::from_utf8_lossy(&response[..split]);
+        let body = &response[split + 4..];
+        assert!(head.contains("\r\nContent-Encoding: gzip"));
+        assert!(head.contains("\r\nVary: Origin, Accept-Encoding\r\n"));
+        assert!(head.contains(&format!("\r\nContent-Length: {}", body.len())));
+        assert_eq!(GZIPPED.load(Ordering::SeqCst), body.len());
+
+        let mut decoded = String::new();
+        GzDecoder::new(body).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, "a".repeat(4096));
+
+        let response = send(
+            addr,
+            "GET /short HTTP/1.1\r\nAccept-Encoding: gzip\r\nConnection: close\r\n\r\n",
+        );
+        assert!(!response.contains("Content-Encoding"));
+        assert!(response.ends_with("\r\n\r\nshort"));
+    }
+
+    #[test]
+    fn route_group() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .group("/api/v1/")
+                .get("/users", get_users)
+                .post("users", post_users)
+                .path("/", |_| Response::new().body("api root"))
+                .finish(),
+        );
+
+        let response = send(addr, "GET /api/v1/users HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("list users"));
+
+        let response = send(addr, "POST /api/v1/users HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("create user"));
+
+        let response = send(addr, "GET /api/v1/ HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("api root"));
+
+        let response = send(addr, "GET /users HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn cors_before_middleware() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .middleware(auth::bearer_auth(|token| token == "secret"))
+                .cors(CorsConfig::new().allow_origin("https://app.example.com"))
+                .get("/users", get_users),
+        );
+
+        let response = send(
+            addr,
+            "OPTIONS /users HTTP/1.1\r\nOrigin: https://app.example.com\r\nAccess-Control-Request-Method: GET\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert!(response.contains(
+            "\r\nAccess-Control-Allow-Origin: https://app.example.com\r\n"
+        ));
+
+        let response = send(
+            addr,
+            "GET /users HTTP/1.1\r\nOrigin: https://app.example.com\r\nAuthorization: Bearer secret\r\n\r\n",
+        );
+        assert!(response.contains(
+            "\r\nAccess-Control-Allow-Origin: https://app.example.com\r\n"
+        ));
+        assert!(response.ends_with("list users"));
+    }
+
+    #[test]
+    fn cors_before_rate_limit() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .cors(CorsConfig::new().allow_origin("https://app.example.com"))
+                .rate_limit(1, Duration::from_secs(60))
+                .get("/users", get_users),
+        );
+
+        // Preflights are answered before they can count against the limit
+        for _ in 0..2 {
+            let response = send(
+                addr,
+                "OPTIONS /users HTTP/1.1\r\nOrigin: https://app.example.com\r\nAccess-Control-Request-Method: GET\r\n\r\n",
+            );
+            assert!(response.starts_with("HTTP/1.1 204 No Content\r\n"));
+        }
+
+        let get =
+            "GET /users HTTP/1.1\r\nOrigin: https://app.example.com\r\n\r\n";
+        assert!(send(addr, get).ends_with("list users"));
+        let response = send(addr, get);
+        assert!(response.starts_with("HTTP/1.1 429 Too Many Requests\r\n"));
+        assert!(response.contains(
+            "\r\nAccess-Control-Allow-Origin: https://app.example.com\r\n"
+        ));
+    }
+
+    #[cfg(feature = "tls")]
+    fn serve_tls(builder: TlsServerBuilder) -> SocketAddr {
+        let addr = builder.builder.local_addrs()[0];
+        thread::spawn(move || builder.listen());
+        addr
+    }
+
+    /// Send a raw request over TLS, trusting the test CA, and read until the
+    /// server closes the connection
+    #[cfg(feature = "tls")]
+    fn send_tls(addr: SocketAddr, request: &str) -> String {
+        let mut roots = rustls::RootCertStore::empty();
+        for cert in rustls_pemfile::certs(
+            &mut include_bytes!("../testdata/ca.pem").as_slice(),
+        ) {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        let config = rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let conn = rustls::ClientConnection::new(
+            Arc::new(config),
+            "localhost".try_into().unwrap(),
+        )
+        .unwrap();
+
+        let mut stream =
+            rustls::StreamOwned::new(conn, TcpStream::connect(addr).unwrap());
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn tls_routes_requests() {
+        let addr = serve_tls(
+            TlsServer::bind(
+                "127.0.0.1:0",
+                concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/key.pem"),
+                concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/cert.pem"),
+            )
+            .unwrap()
+            .path("/users", get_users)
+            .route(Method::Post, "/users", post_users),
+        );
+
+        let response =
+            send_tls(addr, "GET /users HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nlist users"));
+
+        let response =
+            send_tls(addr, "POST /users HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\ncreate user"));
+
+        let response =
+            send_tls(addr, "GET /nope HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn tls_is_secure() {
+        let addr = serve_tls(
+            TlsServer::bind(
+                "127.0.0.1:0",
+                concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/key.pem"),
+                concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/cert.pem"),
+            )
+            .unwrap()
+            .get("/secure", secure),
+        );
+
+        let response =
+            send_tls(addr, "GET /secure HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\ntrue"));
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn tls_default_handler() {
+        let addr = serve_tls(
+            TlsServer::bind(
+                "127.0.0.1:0",
+                concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/key.pem"),
+                concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/cert.pem"),
+            )
+            .unwrap()
+            .get("/users", get_users)
+            .default(|_| Response::new().body("nothing here"))
+            .read_timeout(Duration::from_secs(5))
+            .write_timeout(None),
+        );
+
+        let response = send_tls(
+            addr,
+            "GET /missing HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.ends_with("\r\n\r\nnothing here"));
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn tls_bind_pem() {
+        let addr = serve_tls(
+            TlsServer::bind_pem(
+                "127.0.0.1:0",
+                include_bytes!("../testdata/key.pem"),
+                include_bytes!("../testdata/cert.pem"),
+            )
+            .unwrap()
+            .get("/users", get_users),
+        );
+
+        let response =
+            send_tls(addr, "GET /users HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\nlist users"));
+
+        let result = TlsServer::bind_pem(
+            "127.0.0.1:0",
+            include_bytes!("../testdata/key.pem"),
+            b"not a certificate",
+        );
+        assert!(matches!(result, Err(BindError::InvalidCert(_))));
+
+        let result = TlsServer::bind_pem(
+            "127.0.0.1:0",
+            include_bytes!("../testdata/cert.pem"),
+            include_bytes!("../testdata/cert.pem"),
+        );
+        assert!(matches!(result, Err(BindError::InvalidKey(_))));
+    }
+
+    #[test]
+    fn bind_address_in_use() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let result = Server::bind(addr);
+        assert!(matches!(result, Err(BindError::AddrInUse(_))));
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn tls_bind_missing_files() {
+        let result = TlsServer::bind(
+            "127.0.0.1:0",
+            "/nonexistent/key.pem",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/cert.pem"),
+        );
+        assert!(matches!(result, Err(BindError::InvalidKey(_))));
+    }
+
+    #[test]
+    fn head_request() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .get("/users", get_users),
+        );
+
+        let read_all = |request: &[u8]| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(request).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let get = read_all(b"GET /users HTTP/1.1\r\nConnection: close\r\n\r\n");
+        let head =
+            read_all(b"HEAD /users HTTP/1.1\r\nConnection: close\r\n\r\n");
+
+        assert!(get.ends_with("\r\n\r\nlist users"));
+        assert!(head.contains("\r\nContent-Length: 10\r\n"));
+        assert!(head.ends_with("\r\n\r\n"));
+
+        let without_date = |response: &str| {
+            response
+                .lines()
+                .filter(|line| !line.starts_with("Date: "))
+                .collect::<Vec<_>>()
+                .join("\r\n")
+        };
+        assert_eq!(
+            without_date(&head),
+            without_date(get.trim_end_matches("list users"))
+        );
+    }
+
+    #[test]
+    fn suppress_default_headers() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .get("/users", get_users)
+                .get("/html", |_| {
+                    Response::new()
+                        .header("Content-Type", "text/html")
+                        .body("<p>hi</p>")
+                }),
+        );
+        let response = send(addr, "GET /users HTTP/1.1\r\n\r\n");
+        assert!(response.contains("\r\nServer: wee-http/"));
+        assert!(response
+            .contains("\r\nContent-Type: text/plain; charset=utf-8\r\n"));
+        let response = send(addr, "GET /html HTTP/1.1\r\n\r\n");
+        assert!(response.contains("\r\nContent-Type: text/html\r\n"));
+
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .default_headers(false)
+                .get("/users", get_users),
+        );
+        let response = send(addr, "GET /users HTTP/1.1\r\n\r\n");
+        assert!(!response.contains("Server:"));
+        assert!(!response.contains("Content-Type:"));
+        assert!(response.ends_with("list users"));
+    }
+
+    #[test]
+    fn binary_body() {
+        let addr =
+            serve(Server::bind("127.0.0.1:0").unwrap().get("/image", |_| {
+                Response::new()
+                    .header("Content-Type", "application/octet-stream")
+                    .body([0xff, 0x00, 0xfe, 0xff].as_slice())
+            }));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /image HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+
+        assert!(response.ends_with(b"\r\n\r\n\xff\x00\xfe\xff"));
+        let head = String::from_utf8_lossy(&response[..response.len() - 4]);
+        assert!(head.contains("\r\nContent-Length: 4\r\n"));
+    }
+
+    #[test]
+    fn binary_request_body() {
+        let addr =
+            serve(Server::bind("127.0.0.1:0").unwrap().post("/echo", echo));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\nConnection: close\r\n\r\n\x00\xff\r\n\xc3")
+            .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+
+        assert!(response.starts_with(b"HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(b"\r\n\r\n\x00\xff\r\n\xc3"));
+    }
+
+    /// Hands out `data` one byte per read, like a very slow client
+    struct OneByteReader<'a>(&'a [u8]);
+
+    impl Read for OneByteReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let Some((&first, rest)) = self.0.split_first() else {
+                return Ok(0);
+            };
+            buf[0] = first;
+            self.0 = rest;
+            Ok(1)
+        }
+    }
+
+    impl Write for OneByteReader<'_> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn read_request_byte_by_byte() {
+        let mut reader = OneByteReader(
+            b"POST /users HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello",
+        );
+        let request = read_request(
+            &mut reader,
+            &mut Vec::new(),
+            DEFAULT_MAX_HEADER_BYTES,
+            |_| DEFAULT_MAX_BODY,
+        )
+        .unwrap();
+        assert_eq!(request.path(), "/users");
+        assert_eq!(request.header("host"), Some("localhost"));
+        assert_eq!(request.body(), Some("hello"));
+    }
+
+    #[test]
+    fn headers_too_large() {
+        let request = format!(
+            "GET / HTTP/1.1\r\nX-Big: {}\r\n\r\n",
+            "a".repeat(DEFAULT_MAX_HEADER_BYTES)
+        );
+        let Err(ReadError::Invalid(err)) = read_request(
+            &mut OneByteReader(request.as_bytes()),
+            &mut Vec::new(),
+            DEFAULT_MAX_HEADER_BYTES,
+            |_| DEFAULT_MAX_BODY,
+        ) else {
+            panic!("oversized headers were accepted");
+        };
+        assert_eq!(err, Error::HeadersTooLarge);
+        assert_eq!(err.status_code(), StatusCode::RequestHeaderFieldsTooLarge);
+    }
+
+    #[test]
+    fn reject_oversized_headers() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .max_header_bytes(512)
+                .get("/users", get_users),
+        );
+
+        let response =
+            send(addr, "GET /users HTTP/1.1\r\nX-Small: yes\r\n\r\n");
+        assert!(response.ends_with("list users"));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(
+                format!(
+                    "GET /users HTTP/1.1\r\nX-Big: {}\r\n\r\n",
+                    "a".repeat(500)
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response
+            .starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+        assert!(response.contains("\r\nConnection: close\r\n"));
+        assert!(closed(&mut stream));
+    }
+
+    #[test]
+    fn matched_route() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .get("/users/:id", |request| {
+                    Response::new().body(request.route().unwrap())
+                })
+                .default(|request| {
+                    Response::new().text_body(format!("{:?}", request.route()))
+                })
+                .middleware(|request, next| {
+                    let route = request.route().unwrap_or("none").to_string();
+                    next.run(request).header("X-Route", route)
+                }),
+        );
+
+        let response = send(addr, "GET /users/7 HTTP/1.1\r\n\r\n");
+        assert!(response.contains("\r\nX-Route: /users/:id\r\n"));
+        assert!(response.ends_with("\r\n\r\n/users/:id"));
+
+        let response = send(addr, "GET /groups/7 HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\nNone"));
+    }
+
+    #[test]
+    fn automatic_options() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .get("/users", get_users)
+                .post("/users", post_users)
+                .delete("/users/:id", get_user)
+                .route(Method::Options, "/custom", |_| {
+                    Response::new().body("custom options")
+                }),
+        );
+
+        let response = send(addr, "OPTIONS /users HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert!(response.contains("\r\nAllow: GET, HEAD, OPTIONS, POST\r\n"));
+        assert!(!response.contains("Content-Length"));
+        assert!(response.ends_with("\r\n\r\n"));
+
+        let response = send(addr, "OPTIONS * HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert!(response
+            .contains("\r\nAllow: DELETE, GET, HEAD, OPTIONS, POST\r\n"));
+        assert!(!response.contains("Content-Length"));
+        assert!(response.ends_with("\r\n\r\n"));
+
+        let response = send(addr, "OPTIONS /custom HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("custom options"));
+
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .auto_options(false)
+                .get("/users", get_users),
+        );
+        let response = send(addr, "OPTIONS /users HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(response.contains("\r\nAllow: GET, HEAD\r\n"));
+    }
+
+    #[test]
+    fn auto_head() {
+        let head = |addr, path| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(stream, "HEAD {path} HTTP/1.1\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .get("/users", get_users),
+        );
+        let response = head(addr, "/users");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\nContent-Length: 10\r\n\r\n"));
+
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .auto_head(false)
+                .get("/users", get_users)
+                .route(Method::Head, "/groups", |_| {
+                    Response::new().body("groups")
+                }),
+        );
+        let response = head(addr, "/users");
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(response.contains("\r\nAllow: GET, OPTIONS\r\n"));
+
+        let response = head(addr, "/groups");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\nContent-Length: 6\r\n\r\n"));
+    }
+
+    #[test]
+    fn expect_continue() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .max_body(16)
+                .post("/users", body_len),
+        );
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"POST /users HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        assert_eq!(read_response(&mut stream), "HTTP/1.1 100 Continue\r\n\r\n");
+        stream.write_all(b"hello").unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\n5"));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"POST /users HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 100\r\n\r\n")
+            .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+    }
+
+    #[test]
+    fn pipelined_requests() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .get("/users", get_users)
+                .post("/users", body_len)
+                .get("/users/:id", get_user),
+        );
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(
+                b"GET /users HTTP/1.1\r\n\r\n\
+                POST /users HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n\
+                POST /users HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc\
+                GET /users/7 HTTP/1.1\r\nConnection: close\r\n\r\n",
+            )
+            .unwrap();
+
+        assert!(read_response(&mut stream).ends_with("\r\n\r\nlist users"));
+        assert!(read_response(&mut stream).ends_with("\r\n\r\n5"));
+        assert!(read_response(&mut stream).ends_with("\r\n\r\n3"));
+        assert!(read_response(&mut stream).ends_with("\r\n\r\nuser 7"));
+        assert!(closed(&mut stream));
+    }
+
+    #[test]
+    fn serve_router() {
+        let router = Router::new()
+            .add(Method::Get, "/users/:id", get_user)
+            .default(|_| Response::new().body("fallback"));
+        let addr = serve(Server::bind("127.0.0.1:0").unwrap().router(router));
+
+        let response = send(addr, "GET /users/7 HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\nuser 7"));
+        let response = send(addr, "GET /groups HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\nfallback"));
+    }
+
+    #[test]
+    fn conditional_get() {
+        let addr = serve(Server::bind("127.0.0.1:0").unwrap().get("/", |_| {
+            Response::from_file(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/testdata/index.html"
+            ))
+            .unwrap()
+        }));
+
+        let response = send(addr, "GET / HTTP/1.1\r\n\r\n");
+        let etag = response
+            .lines()
+            .find_map(|line| line.strip_prefix("ETag: "))
+            .unwrap()
+            .to_string();
+        assert!(response.contains("\r\nAccept-Ranges: bytes\r\n"));
+        assert!(response.ends_with("<p>Hello from a file</p>\n"));
+
+        let request = format!(
+            "GET / HTTP/1.1\r\nIf-None-Match: \"other\", {etag}\r\n\r\n"
+        );
+        let response = send(addr, &request);
+        assert!(response.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert!(response.contains(&format!("\r\nETag: {etag}\r\n")));
+        assert!(!response.contains("Content-Length"));
+        assert!(response.ends_with("\r\n\r\n"));
+
+        let response =
+            send(addr, "GET / HTTP/1.1\r\nIf-None-Match: W/\"0-0\"\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("<p>Hello from a file</p>\n"));
+    }
+
+    #[test]
+    fn range_requests() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .get("/", |_| {
+                    Response::new()
+                        .header("Accept-Ranges", "bytes")
+                        .header("ETag", "\"v2\"")
+                        .header(
+                            "Last-Modified",
+                            "Sun, 06 Nov 1994 08:49:37 GMT",
+                        )
+                        .body("0123456789")
+                })
+                .get("/dynamic", |_| Response::new().body("0123456789")),
+        );
+
+        let response = send(addr, "GET / HTTP/1.1\r\nRange: bytes=2-5\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(response.contains("\r\nContent-Range: bytes 2-5/10\r\n"));
+        assert!(response.ends_with("\r\n\r\n2345"));
+
+        let response = send(addr, "GET / HTTP/1.1\r\nRange: bytes=7-\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(response.contains("\r\nContent-Range: bytes 7-9/10\r\n"));
+        assert!(response.ends_with("\r\n\r\n789"));
+
+        let response = send(addr, "GET / HTTP/1.1\r\nRange: bytes=10-\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"));
+        assert!(response.contains("\r\nContent-Range: bytes */10\r\n"));
+        assert!(response.contains("\r\nContent-Length: 0\r\n"));
+
+        let response =
+            send(addr, "GET / HTTP/1.1\r\nRange: bytes=0-1,4-5\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\n0123456789"));
+
+        // Without Accept-Ranges the handler's body is sent whole
+        let response =
+            send(addr, "GET /dynamic HTTP/1.1\r\nRange: bytes=0-3\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\n0123456789"));
+
+        let ranged = |if_range: &str| {
+            send(
+                addr,
+                &format!(
+                    "GET / HTTP/1.1\r\nRange: bytes=0-3\r\nIf-Range: {if_range}\r\n\r\n"
+                ),
+            )
+        };
+        for current in ["\"v2\"", "Sun, 06 Nov 1994 08:49:37 GMT"] {
+            let response = ranged(current);
+            assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+            assert!(response.ends_with("\r\n\r\n0123"));
+        }
+        for stale in ["\"v1\"", "W/\"v2\"", "Sat, 05 Nov 1994 08:49:37 GMT"] {
+            let response = ranged(stale);
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(response.ends_with("\r\n\r\n0123456789"));
+        }
+    }
+
+    #[test]
+    fn websocket_upgrade() {
+        let addr =
+            serve(Server::bind("127.0.0.1:0").unwrap().get("/ws", |request| {
+                websocket::upgrade(&request, |stream| {
+                    let mut message = [0; 5];
+                    stream.read_exact(&mut message).unwrap();
+                    stream.write_all(&message).unwrap();
+                })
+            }));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(
+                b"GET /ws HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+                Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                Sec-WebSocket-Version: 13\r\n\r\n",
+            )
+            .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(response.contains("\r\nUpgrade: websocket\r\n"));
+        assert!(response.contains("\r\nConnection: Upgrade\r\n"));
+        assert!(response.contains(
+            "\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"
+        ));
+
+        stream.write_all(b"hello").unwrap();
+        let mut echoed = String::new();
+        stream.read_to_string(&mut echoed).unwrap();
+        assert_eq!(echoed, "hello");
+
+        // A message sent along with the handshake isn't lost
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(
+                b"GET /ws HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+                Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                Sec-WebSocket-Version: 13\r\n\r\nhello",
+            )
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(response.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn complete_body_before_close() {
+        let body = "wee-http ".repeat(100_000);
+        let expected = body.clone();
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .get("/big", move |_| Response::new().body(body.clone())),
+        );
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /big HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response
+            .contains(&format!("\r\nContent-Length: {}\r\n", expected.len())));
+        assert!(response.ends_with(&format!("\r\n\r\n{expected}")));
+    }
+
+    #[test]
+    fn peer_addr() {
+        fn client(request: Request) -> Response {
+            Response::new().text_body(format!(
+                "{} {}",
+                request.peer_addr().unwrap().ip(),
+                request.client_ip().unwrap()
+            ))
+        }
+        let addr = serve(Server::bind("127.0.0.1:0").unwrap().get("/", client));
+        let response = send(
+            addr,
+            "GET / HTTP/1.1\r\nX-Forwarded-For: 203.0.113.7\r\n\r\n",
+        );
+        assert!(response.ends_with("\r\n\r\n127.0.0.1 127.0.0.1"));
+
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .trust_forwarded_for(true)
+                .get("/", client),
+        );
+        let response = send(
+            addr,
+            "GET / HTTP/1.1\r\nX-Forwarded-For: 198.51.100.1, 203.0.113.7\r\n\r\n",
+        );
+        assert!(response.ends_with("\r\n\r\n127.0.0.1 203.0.113.7"));
+        let response = send(addr, "GET / HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\n127.0.0.1 127.0.0.1"));
+    }
+
+    #[test]
+    fn rate_limited() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .rate_limit(3, Duration::from_secs(60))
+                .get("/users", get_users),
+        );
+
+        for _ in 0..3 {
+            let response = send(addr, "GET /users HTTP/1.1\r\n\r\n");
+            assert!(response.ends_with("list users"));
+        }
+        let response = send(addr, "GET /users HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 429 Too Many Requests\r\n"));
+        assert!(response.contains("\r\nRetry-After: 20\r\n"));
+    }
+
+    #[test]
+    fn route_rate_limited() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .get("/users", get_users)
+                .post(
+                    "/users",
+                    rate_limit::limit(1, Duration::from_secs(60), post_users),
+                ),
+        );
+
+        let response =
+            send(addr, "POST /users HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+        assert!(response.ends_with("create user"));
+        let response =
+            send(addr, "POST /users HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 429 Too Many Requests\r\n"));
+        assert!(response.contains("\r\nRetry-After: 60\r\n"));
+
+        let response = send(addr, "GET /users HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("list users"));
+    }
+
+    #[test]
+    fn max_connections() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .max_connections(1)
+                .get("/users", get_users),
+        );
+
+        let mut first = TcpStream::connect(addr).unwrap();
+        first.write_all(b"GET /users HTTP/1.1\r\n\r\n").unwrap();
+        assert!(read_response(&mut first).ends_with("list users"));
+
+        let mut second = TcpStream::connect(addr).unwrap();
+        let response = read_response(&mut second);
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(closed(&mut second));
+
+        // The slot is free again once the first connection has closed
+        drop(first);
+        let response = (0..100)
+            .map(|_| {
+                thread::sleep(Duration::from_millis(10));
+                send(addr, "GET /users HTTP/1.1\r\n\r\n")
+            })
+            .find(|response| response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.is_some());
+    }
+
+    /// A writer that can be read from the test while the server writes to it
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuf {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    #[test]
+    fn access_log() {
+        let log = SharedBuf::default();
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .access_log(log.clone())
+                .get("/users", get_users),
+        );
+
+        send(addr, "GET /users HTTP/1.1\r\n\r\n");
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"HEAD /users HTTP/1.0\r\n\r\n").unwrap();
+        stream.read_to_end(&mut Vec::new()).unwrap();
+        send(addr, "GET /missing HTTP/1.1\r\n\r\n");
+
+        // Lines are written once the response has been sent
+        for _ in 0..100 {
+            if log.contents().lines().count() == 3 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let log = log.contents();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 3);
+        let (client, rest) = lines[0].split_once(" [").unwrap();
+        assert_eq!(client, "127.0.0.1 - -");
+        let (date, rest) = rest.split_once("] ").unwrap();
+        assert_eq!(date.len(), "06/Nov/1994:08:49:37 +0000".len());
+        assert!(date.ends_with(" +0000"));
+        assert_eq!(rest, "\"GET /users HTTP/1.1\" 200 10");
+        assert!(lines[1].ends_with("] \"HEAD /users HTTP/1.0\" 200 -"));
+        assert!(lines[2].contains("] \"GET /missing HTTP/1.1\" 404 "));
+    }
+
+    static SENT: AtomicUsize = AtomicUsize::new(0);
+
+    #[test]
+    fn on_response_bytes() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .on_response(|status, written| {
+                    assert_eq!(status, StatusCode::Ok);
+                    SENT.fetch_add(written, Ordering::SeqCst);
+                })
+                .get("/users", get_users)
+                .get("/stream", |_| {
+                    Response::stream(
+                        ["hello", " world"].map(Into::into).into_iter(),
+                    )
+                }),
+        );
+
+        send(addr, "GET /users HTTP/1.1\r\nConnection: close\r\n\r\n");
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /stream HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.ends_with("\r\n0\r\n\r\n"));
+
+        let expected = "list users".len() + "hello world".len();
+        for _ in 0..100 {
+            if SENT.load(Ordering::SeqCst) == expected {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(SENT.load(Ordering::SeqCst), expected);
+    }
+
+    #[test]
+    fn test_request() {
+        let server = Server::bind("127.0.0.1:0")
+            .unwrap()
+            .middleware(|request, next| {
+                next.run(request).header("X-Middleware", "yes")
+            })
+            .get("/users", get_users)
+            .post("/users", body_len)
+            .get("/users/:id", get_user);
+
+        let response = server.test_request("GET /users/7 HTTP/1.1\r\n\r\n");
+        assert_eq!(*response.status_code(), StatusCode::Ok);
+        assert_eq!(response.header_value("x-middleware"), Some("yes"));
+        assert!(String::from_utf8(response.serialise())
+            .unwrap()
+            .ends_with("\r\n\r\nuser 7"));
+
+        let response = server.test_request(
+            "POST /users HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello",
+        );
+        assert!(String::from_utf8(response.serialise())
+            .unwrap()
+            .ends_with("\r\n\r\n5"));
+
+        let response = server.test_request("DELETE /users HTTP/1.1\r\n\r\n");
+        assert_eq!(*response.status_code(), StatusCode::MethodNotAllowed);
+
+        let response = server.test_request("GET /groups HTTP/1.1\r\n\r\n");
+        assert_eq!(*response.status_code(), StatusCode::NotFound);
+
+        let response = server.test_request("GET /users\r\n\r\n");
+        assert_eq!(*response.status_code(), StatusCode::BadRequest);
+    }
+
+    /// Describes the request it was sent, as an upstream for proxy tests
+    fn upstream_echo(request: Request) -> Response {
+        let response = Response::new()
+            .header("X-Upstream", "yes")
+            .header("Keep-Alive", "timeout=5");
+        response.body(format!(
+            "{} {} {} {}",
+            request.method_str(),
+            request.request_target(),
+            request.header("x-test").unwrap_or("-"),
+            request.body().unwrap()
+        ))
+    }
+
+    #[test]
+    fn proxy() {
+        let upstream = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .path("/", upstream_echo)
+                .path("/*path", upstream_echo),
+        );
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .proxy("/api", &format!("http://{upstream}"))
+                .get("/users", get_users),
+        );
+
+        let response = send(
+            addr,
+            "POST /api/users?page=2 HTTP/1.1\r\nX-Test: sent\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello",
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\r\nX-Upstream: yes\r\n"));
+        assert!(!response.contains("Keep-Alive"));
+        assert!(response.ends_with("\r\n\r\nPOST /users?page=2 sent hello"));
+
+        let response =
+            send(addr, "GET /api HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\nGET / - "));
+
+        let response =
+            send(addr, "GET /users HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.ends_with("list users"));
+    }
+
+    #[test]
+    fn proxy_unreachable() {
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream = closed.local_addr().unwrap();
+        drop(closed);
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .proxy("/api", &format!("http://{upstream}")),
+        );
+
+        let response =
+            send(addr, "GET /api/users HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 502 Bad Gateway\r\n"));
+    }
+
+    #[test]
+    fn client() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .get("/users", get_users)
+                .path("/echo/*path", upstream_echo),
+        );
+        let client = Client::new().header("X-Test", "sent");
+
+        let response = client.get(&format!("http://{addr}/users")).unwrap();
+        assert_eq!(*response.status_code(), StatusCode::Ok);
+        assert_eq!(response.body_bytes(), b"list users");
+
+        let response = client
+            .request(Method::Post, &format!("http://{addr}/echo/a?b=c"), "hi")
+            .unwrap();
+        assert_eq!(response.header_value("x-upstream"), Some("yes"));
+        assert_eq!(response.body_bytes(), b"POST /echo/a?b=c sent hi");
+
+        let response = client.get(&format!("http://{addr}/missing")).unwrap();
+        assert_eq!(*response.status_code(), StatusCode::NotFound);
+
+        let response = client
+            .request(Method::Head, &format!("http://{addr}/users"), "")
+            .unwrap();
+        assert_eq!(response.header_value("content-length"), Some("10"));
+        assert!(response.body_bytes().is_empty());
+
+        let err = client.get("https://example.com").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn client_timeout() {
+        // Accepts connections and never answers
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = silent.local_addr().unwrap();
+
+        let client = Client::new().timeout(Duration::from_millis(100));
+        let err = client.get(&format!("http://{addr}/")).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock
+        ));
+        drop(silent);
+    }
+
+    #[test]
+    fn client_closes_mid_request() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .workers(1)
+                .get("/users", get_users),
+        );
+
+        // Connecting and leaving straight away
+        drop(TcpStream::connect(addr).unwrap());
+        // Leaving part way through the headers
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /users HTTP/1.1\r\nHo").unwrap();
+        drop(stream);
+        // Closing without reading the response resets the connection
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /users HTTP/1.1\r\n\r\n").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        drop(stream);
+
+        // The only worker is still there to answer
+        let response =
+            send(addr, "GET /users HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.ends_with("list users"));
+    }
+
+    static PANICS: AtomicUsize = AtomicUsize::new(0);
+
+    #[test]
+    fn handler_panics() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .workers(1)
+                .on_panic(|message| {
+                    assert_eq!(message, "user 7 is cursed");
+                    PANICS.fetch_add(1, Ordering::SeqCst);
+                })
+                .get("/users/:id", |request: Request| -> Response {
+                    panic!("user {} is cursed", request.param("id").unwrap())
+                })
+                .get("/users", get_users),
+        );
+
+        let response = send(addr, "GET /users/7 HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(!response.contains("cursed"));
+        assert_eq!(PANICS.load(Ordering::SeqCst), 1);
+
+        let response =
+            send(addr, "GET /users HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.ends_with("list users"));
+    }
+
+    #[test]
+    fn stream_panics() {
+        static PANICS: AtomicUsize = AtomicUsize::new(0);
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .workers(1)
+                .on_panic(|message| {
+                    assert_eq!(message, "ran dry");
+                    PANICS.fetch_add(1, Ordering::SeqCst);
+                })
+                .get("/stream", |_| {
+                    Response::stream((0..2).map(|n| match n {
+                        0 => b"first".to_vec(),
+                        _ => panic!("ran dry"),
+                    }))
+                })
+                .get("/users", get_users),
+        );
+
+        // The body is cut off and the connection closed
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /stream HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(!response.ends_with("0\r\n\r\n"));
+        assert_eq!(PANICS.load(Ordering::SeqCst), 1);
+
+        // The only worker is still there to answer
+        let response =
+            send(addr, "GET /users HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.ends_with("list users"));
+    }
+
+    #[test]
+    fn method_override() {
+        let server = Server::bind("127.0.0.1:0")
+            .unwrap()
+            .post("/users", post_users)
+            .delete("/users", |_| Response::new().body("delete users"));
+        let overridden = |server: &ServerBuilder, method| {
+            let request = format!(
+                "POST /users HTTP/1.1\r\nX-HTTP-Method-Override: {method}\r\n\r\n"
+            );
+            server.test_request(request).body_bytes().to_vec()
+        };
+
+        assert_eq!(overridden(&server, "DELETE"), b"create user");
+
+        let server = server.method_override(true);
+        assert_eq!(overridden(&server, "DELETE"), b"delete users");
+        assert_eq!(overridden(&server, "delete"), b"delete users");
+        // Only the unsafe methods can be asked for
+        assert_eq!(overridden(&server, "GET"), b"create user");
+        assert_eq!(overridden(&server, "CONNECT"), b"create user");
+
+        let request =
+            "GET /users HTTP/1.1\r\nX-HTTP-Method-Override: POST\r\n\r\n";
+        let response = server.test_request(request);
+        assert_eq!(*response.status_code(), StatusCode::MethodNotAllowed);
+    }
+
+    fn secure(request: Request) -> Response {
+        Response::new().text_body(request.is_secure())
+    }
+
+    #[test]
+    fn is_secure() {
+        let forwarded = "GET /secure HTTP/1.1\r\nX-Forwarded-Proto: https\r\nConnection: close\r\n\r\n";
+        let addr =
+            serve(Server::bind("127.0.0.1:0").unwrap().get("/secure", secure));
+        assert!(send(
+            addr,
+            "GET /secure HTTP/1.1\r\nConnection: close\r\n\r\n"
+        )
+        .ends_with("\r\n\r\nfalse"));
+        // Clients can't claim to be secure without a trusted proxy
+        assert!(send(addr, forwarded).ends_with("\r\n\r\nfalse"));
+
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .trust_forwarded_for(true)
+                .get("/secure", secure),
+        );
+        assert!(send(addr, forwarded).ends_with("\r\n\r\ntrue"));
+        let downgraded = "GET /secure HTTP/1.1\r\nX-Forwarded-Proto: http\r\nConnection: close\r\n\r\n";
+        assert!(send(addr, downgraded).ends_with("\r\n\r\nfalse"));
+    }
+
+    #[test]
+    fn https_redirect() {
+        let server = Server::bind("127.0.0.1:0")
+            .unwrap()
+            .https_redirect(true)
+            .trust_forwarded_for(true)
+            .get("/users", get_users);
+
+        let response = server.test_request(
+            "GET /users?page=2 HTTP/1.1\r\nHost: example.com:8080\r\n\r\n",
+        );
+        assert_eq!(*response.status_code(), StatusCode::MovedPermanently);
+        assert_eq!(
+            response.header_value("location"),
+            Some("https://example.com/users?page=2")
+        );
+
+        let response = server
+            .test_request("GET /users HTTP/1.1\r\nHost: [::1]:8080\r\n\r\n");
+        assert_eq!(
+            response.header_value("location"),
+            Some("https://[::1]/users")
+        );
+
+        let response = server.test_request("GET /users HTTP/1.1\r\n\r\n");
+        assert_eq!(*response.status_code(), StatusCode::BadRequest);
+
+        let response = server.test_request(
+            "GET /users HTTP/1.1\r\nHost: example.com\r\nX-Forwarded-Proto: https\r\n\r\n",
+        );
+        assert_eq!(response.body_bytes(), b"list users");
+    }
+
+    #[test]
+    fn accept_transient_errors() {
+        use std::io::{Error, ErrorKind};
+
+        let transient = || {
+            [
+                Error::from(ErrorKind::ConnectionAborted),
+                Error::from(ErrorKind::ConnectionReset),
+                Error::from(ErrorKind::Interrupted),
+                #[cfg(unix)]
+                Error::from_raw_os_error(24),
+            ]
+            .into_iter()
+            .map(Err)
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap());
+        let stream = listener.accept().map(|(stream, _)| stream);
+
+        // The loop carries on past transient errors to the connection after
+        let mut accepted = 0;
+        let incoming = transient().chain([stream]).chain(transient());
+        let result = accept_loop(incoming, None, |_| accepted += 1);
+        assert!(result.is_ok());
+        assert_eq!(accepted, 1);
+
+        let mut tried = 0;
+        let incoming = transient()
+            .chain([Err(Error::from(ErrorKind::InvalidInput))])
+            .chain(transient())
+            .inspect(|_| tried += 1);
+        let result = accept_loop(incoming, None, |_| {});
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(tried, transient().count() + 1);
+
+        // Every error reaches the hook, transient or not
+        static ERRORS: AtomicUsize = AtomicUsize::new(0);
+        static FATAL: AtomicUsize = AtomicUsize::new(0);
+        let incoming = transient()
+            .chain([Err(Error::from(ErrorKind::InvalidInput))])
+            .chain(transient());
+        let on_error: fn(&Error) = |err| {
+            ERRORS.fetch_add(1, Ordering::SeqCst);
+            if err.kind() == ErrorKind::InvalidInput {
+                FATAL.fetch_add(1, Ordering::SeqCst);
+            }
+        };
+        let _ = accept_loop(incoming, Some(on_error), |_| {});
+        assert_eq!(ERRORS.load(Ordering::SeqCst), transient().count() + 1);
+        assert_eq!(FATAL.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn bind_all() {
+        let server = Server::bind_all(["127.0.0.1:0", "[::1]:0"])
+            .unwrap()
+            .get("/users", get_users);
+        let addrs = server.local_addrs();
+        assert_eq!(addrs.len(), 2);
+        assert!(addrs[0].is_ipv6());
+        thread::spawn(move || server.listen());
+        for addr in addrs {
+            let response =
+                send(addr, "GET /users HTTP/1.1\r\nConnection: close\r\n\r\n");
+            assert!(response.ends_with("list users"));
+        }
+
+        // The same for a spawned server, which polls the listeners in turn
+        let server = Server::bind_all(["127.0.0.1:0", "[::1]:0"])
+            .unwrap()
+            .get("/users", get_users);
+        let addrs = server.local_addrs();
+        let handle = server.spawn().unwrap();
+        for addr in addrs {
+            let response =
+                send(addr, "GET /users HTTP/1.1\r\nConnection: close\r\n\r\n");
+            assert!(response.ends_with("list users"));
+        }
+        handle.shutdown();
+    }
+
+    #[test]
+    fn bind_all_dual_stack() {
+        let port = TcpListener::bind("[::]:0").unwrap().local_addr().unwrap();
+        let port = port.port();
+        let server = Server::bind_all([
+            format!("0.0.0.0:{port}"),
+            format!("[::]:{port}"),
+        ])
+        .unwrap()
+        .get("/users", get_users);
+        thread::spawn(move || server.listen());
+
+        for addr in [format!("127.0.0.1:{port}"), format!("[::1]:{port}")] {
+            let response = send(
+                addr.parse().unwrap(),
+                "GET /users HTTP/1.1\r\nConnection: close\r\n\r\n",
+            );
+            assert!(response.ends_with("list users"));
+        }
+    }
+
+    #[test]
+    fn route_max_body() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .max_body(1024)
+                .post("/upload", body_len)
+                .post("/users", body_len)
+                .route_max_body("/upload", 1024 * 1024),
+        );
+        let post = |path: &str, len: usize| {
+            let request = format!(
+                "POST {path} HTTP/1.1\r\nContent-Length: {len}\r\nConnection: close\r\n\r\n{}",
+                "a".repeat(len)
+            );
+            send(addr, &request)
+        };
+
+        let response = post("/upload", 1024 * 1024);
+        assert!(response.ends_with(&format!("\r\n\r\n{}", 1024 * 1024)));
+        let response = post("/upload", 1024 * 1024 + 1);
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+
+        let response = post("/users", 1024);
+        assert!(response.ends_with("\r\n\r\n1024"));
+        let response = post("/users", 1025);
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+    }
+
+    #[test]
+    fn route_max_body_leaves_routing_alone() {
+        let addr = serve(
+            Server::bind("127.0.0.1:0")
+                .unwrap()
+                .max_body(16)
+                .post("/files/*path", body_len)
+                .route_max_body("/files/big", 1024)
+                .route_max_body("/nothing", 1024),
+        );
+
+        let response = send(
+            addr,
+            &format!(
+                "POST /files/big HTTP/1.1\r\nContent-Length: 100\r\nConnection: close\r\n\r\n{}",
+                "a".repeat(100)
+            ),
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\n100"));
+
+        let response = send(
+            addr,
+            &format!(
+                "POST /files/small HTTP/1.1\r\nContent-Length: 100\r\nConnection: close\r\n\r\n{}",
+                "a".repeat(100)
+            ),
+        );
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+
+        let response =
+            send(addr, "GET /nothing HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn request_ids() {
+        let server = Server::bind("127.0.0.1:0")
+            .unwrap()
+            .get("/id", |request| Response::new().body(request.id()));
+
+        let response = server.test_request("GET /id HTTP/1.1\r\n\r\n");
+        assert_eq!(response.header_value("x-request-id"), None);
+        assert!(response.body_bytes().is_empty());
+
+        let addr = serve(server.request_ids(true));
+        let ids = (0..2)
+            .map(|_| {
+                let response =
+                    send(addr, "GET /id HTTP/1.1\r\nConnection: close\r\n\r\n");
+                let id = response
+                    .lines()
+                    .find_map(|line| line.strip_prefix("X-Request-Id: "))
+                    .unwrap()
+                    .to_string();
+                assert!(response.ends_with(&format!("\r\n\r\n{id}")));
+                id
+            })
+            .collect::<Vec<_>>();
+        assert_ne!(ids[0], ids[1]);
+
+        let response = send(
+            addr,
+            "GET /id HTTP/1.1\r\nX-Request-Id: abc-123\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.contains("\r\nX-Request-Id: abc-123\r\n"));
+        assert!(response.ends_with("\r\n\r\nabc-123"));
+
+        let request = format!(
+            "GET /id HTTP/1.1\r\nX-Request-Id: {}\r\nConnection: close\r\n\r\n",
+            "a".repeat(129)
+        );
+        assert!(!send(addr, &request).contains(&"a".repeat(129)));
     }
 }
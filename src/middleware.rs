@@ -0,0 +1,35 @@
+use crate::{Request, Response};
+use std::sync::Arc;
+
+pub(crate) type BoxedMiddleware =
+    Arc<dyn Fn(Request, Next) -> Response + Send + Sync>;
+
+/// The rest of the chain after a middleware, see
+/// [`crate::ServerBuilder::middleware`]
+pub struct Next<'a> {
+    middleware: &'a [BoxedMiddleware],
+    endpoint: &'a dyn Fn(Request) -> Response,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(
+        middleware: &'a [BoxedMiddleware],
+        endpoint: &'a dyn Fn(Request) -> Response,
+    ) -> Self {
+        Self {
+            middleware,
+            endpoint,
+        }
+    }
+
+    /// Pass `request` on to the next middleware, or the handler once there are
+    /// none left
+    pub fn run(self, request: Request) -> Response {
+        match self.middleware.split_first() {
+            Some((middleware, rest)) => {
+                middleware(request, Next::new(rest, self.endpoint))
+            }
+            None => (self.endpoint)(request),
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! Guessing the `Content-Type` of a body
+
+use std::path::Path;
+
+/// The media type for the extension of `path`, e.g. `text/css` for
+/// `static/site.css`
+pub fn from_path(path: impl AsRef<Path>) -> Option<&'static str> {
+    let extension = path.as_ref().extension()?.to_str()?.to_lowercase();
+    let content_type = match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "png" => "image/png",
+        "svg" => "image/svg+xml",
+        "txt" => "text/plain; charset=utf-8",
+        _ => return None,
+    };
+    Some(content_type)
+}
+
+/// The media type `body` looks like, falling back to plain text or raw bytes
+/// when nothing more specific is recognised
+pub fn sniff(body: &[u8]) -> &'static str {
+    let Ok(text) = std::str::from_utf8(body) else {
+        if body.starts_with(b"\x89PNG\r\n\x1a\n") {
+            return "image/png";
+        }
+        return "application/octet-stream";
+    };
+
+    let text = text.trim_start().to_lowercase();
+    if text.starts_with("<!doctype html") || text.starts_with("<html") {
+        "text/html; charset=utf-8"
+    } else if text.starts_with("<svg") {
+        "image/svg+xml"
+    } else if text.starts_with('{') || text.starts_with('[') {
+        "application/json"
+    } else {
+        "text/plain; charset=utf-8"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extension() {
+        assert_eq!(from_path("index.html"), Some("text/html; charset=utf-8"));
+        assert_eq!(
+            from_path("static/css/site.CSS"),
+            Some("text/css; charset=utf-8")
+        );
+        assert_eq!(from_path("logo.png"), Some("image/png"));
+        assert_eq!(from_path("data.json"), Some("application/json"));
+        assert_eq!(from_path("archive.tar.gz"), None);
+        assert_eq!(from_path("README"), None);
+    }
+
+    #[test]
+    fn sniff_body() {
+        assert_eq!(
+            sniff(b"<!DOCTYPE html><p>hi</p>"),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(sniff(b"  {\"a\": 1}"), "application/json");
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\x00\xff"), "image/png");
+        assert_eq!(sniff(b"hello"), "text/plain; charset=utf-8");
+        assert_eq!(sniff(&[0xff, 0x00]), "application/octet-stream");
+    }
+}
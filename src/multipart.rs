@@ -0,0 +1,177 @@
+use crate::Error;
+
+/// One field of a `multipart/form-data` body, see
+/// [`crate::Request::multipart`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Part {
+    name: String,
+    filename: Option<String>,
+    content_type: String,
+    data: Vec<u8>,
+}
+
+impl Part {
+    /// The name of the form field
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The name of the uploaded file, `None` for a plain field
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    /// The media type of the data, `text/plain` unless the part says
+    /// otherwise
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    /// The data exactly as it was sent
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The data as text, `None` if it isn't valid UTF-8
+    pub fn text(&self) -> Option<&str> {
+        std::str::from_utf8(&self.data).ok()
+    }
+}
+
+/// Split `body` into the parts separated by `boundary`
+pub(crate) fn parse(body: &[u8], boundary: &str) -> Result<Vec<Part>, Error> {
+    let delimiter = format!("\r\n--{boundary}").into_bytes();
+    // The first delimiter can start the body without a line break before it
+    let first = find(body, &delimiter[2..]).ok_or(Error::Malformed)?;
+    let mut rest = &body[first + delimiter.len() - 2..];
+
+    let mut parts = Vec::new();
+    loop {
+        if rest.starts_with(b"--") {
+            return Ok(parts);
+        }
+        // `rest` starts with the line break ending the delimiter, so a part
+        // without headers still has the blank line to find
+        if !rest.starts_with(b"\r\n") {
+            return Err(Error::Malformed);
+        }
+        let headers_end = find(rest, b"\r\n\r\n").ok_or(Error::Malformed)?;
+        let headers = std::str::from_utf8(&rest[2..headers_end.max(2)])
+            .map_err(|_| Error::InvalidUtf8)?;
+        let data = &rest[headers_end + 4..];
+        let data_end = find(data, &delimiter).ok_or(Error::Malformed)?;
+        parts.push(part(headers, &data[..data_end])?);
+        rest = &data[data_end + delimiter.len()..];
+    }
+}
+
+/// A part from its headers and data
+fn part(headers: &str, data: &[u8]) -> Result<Part, Error> {
+    let mut disposition = None;
+    let mut content_type = None;
+    for header in headers.split("\r\n").filter(|line| !line.is_empty()) {
+        let (key, value) = header.split_once(':').ok_or(Error::Malformed)?;
+        let key = key.trim();
+        if key.eq_ignore_ascii_case("content-disposition") {
+            disposition = Some(value.trim());
+        } else if key.eq_ignore_ascii_case("content-type") {
+            content_type = Some(value.trim());
+        }
+    }
+
+    let (kind, params) = disposition
+        .ok_or(Error::Malformed)?
+        .split_once(';')
+        .ok_or(Error::Malformed)?;
+    if !kind.trim().eq_ignore_ascii_case("form-data") {
+        return Err(Error::Malformed);
+    }
+    Ok(Part {
+        name: param(params, "name").ok_or(Error::Malformed)?,
+        filename: param(params, "filename"),
+        content_type: content_type.unwrap_or("text/plain").into(),
+        data: data.to_vec(),
+    })
+}
+
+/// The value of the parameter `name` in `;` separated `params` such as
+/// `name="file"; filename="a.txt"`, unquoted
+pub(crate) fn param(params: &str, name: &str) -> Option<String> {
+    params.split(';').find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        if !key.trim().eq_ignore_ascii_case(name) {
+            return None;
+        }
+        let value = value.trim();
+        let quoted = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'));
+        Some(match quoted {
+            Some(quoted) => quoted.replace("\\\"", "\"").replace("\\\\", "\\"),
+            None => value.into(),
+        })
+    })
+}
+
+/// Where `needle` first appears in `haystack`
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_and_file() {
+        let body = b"--xyz\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\
+            \r\n\
+            holiday\r\n\
+            --xyz\r\n\
+            Content-Disposition: form-data; name=\"photo\"; filename=\"beach.png\"\r\n\
+            Content-Type: image/png\r\n\
+            \r\n\
+            \x89PNG\r\n\x00\xff\r\n\
+            --xyz--\r\n";
+
+        let parts = parse(body, "xyz").unwrap();
+        assert_eq!(parts.len(), 2);
+
+        assert_eq!(parts[0].name(), "title");
+        assert_eq!(parts[0].filename(), None);
+        assert_eq!(parts[0].content_type(), "text/plain");
+        assert_eq!(parts[0].text(), Some("holiday"));
+
+        assert_eq!(parts[1].name(), "photo");
+        assert_eq!(parts[1].filename(), Some("beach.png"));
+        assert_eq!(parts[1].content_type(), "image/png");
+        assert_eq!(parts[1].data(), b"\x89PNG\r\n\x00\xff");
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(parse(b"no boundary here", "xyz"), Err(Error::Malformed));
+        assert_eq!(
+            parse(b"--xyz\r\n\r\nno disposition\r\n--xyz--", "xyz"),
+            Err(Error::Malformed)
+        );
+        assert_eq!(
+            parse(
+                b"--xyz\r\nContent-Disposition: form-data; name=a\r\n\r\nnever ends",
+                "xyz"
+            ),
+            Err(Error::Malformed)
+        );
+    }
+
+    #[test]
+    fn quoted_params() {
+        let params = " name=\"a \\\"b\\\"\"; filename=c.txt";
+        assert_eq!(param(params, "name").unwrap(), "a \"b\"");
+        assert_eq!(param(params, "FILENAME").unwrap(), "c.txt");
+        assert_eq!(param(params, "size"), None);
+    }
+}
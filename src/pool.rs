@@ -0,0 +1,84 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed number of threads that run jobs sent to them in order. A job that
+/// panics ends without taking its thread with it. Dropping the pool waits for
+/// the queued jobs to finish.
+pub(crate) struct ThreadPool {
+    workers: Vec<JoinHandle<()>>,
+    sender: Option<Sender<Job>>,
+}
+
+impl ThreadPool {
+    pub(crate) fn new(size: usize) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..size.max(1))
+            .map(|_| {
+                let receiver = receiver.clone();
+                thread::spawn(move || Self::work(receiver))
+            })
+            .collect();
+
+        Self {
+            workers,
+            sender: Some(sender),
+        }
+    }
+
+    /// Queue `job`, which is dropped unrun if no worker is left to take it
+    pub(crate) fn execute(&self, job: impl FnOnce() + Send + 'static) {
+        if let Some(sender) = &self.sender {
+            if sender.send(Box::new(job)).is_err() {
+                log::error!("no workers left, dropping a job");
+            }
+        }
+    }
+
+    fn work(receiver: Arc<Mutex<Receiver<Job>>>) {
+        loop {
+            let job = receiver.lock().unwrap().recv();
+            match job {
+                Ok(job) => {
+                    if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                        log::error!("a worker's job panicked");
+                    }
+                }
+                // The pool has been dropped
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn survives_panicking_jobs() {
+        let pool = ThreadPool::new(1);
+        let (sender, receiver) = mpsc::channel();
+        pool.execute(|| panic!("job failed"));
+        pool.execute(move || sender.send("still working").unwrap());
+        assert_eq!(receiver.recv().unwrap(), "still working");
+    }
+}
@@ -0,0 +1,232 @@
+//! Forwarding requests to an upstream server, see
+//! [`crate::ServerBuilder::proxy`]
+
+use crate::{client, Method, Request, Response, StatusCode};
+use std::{io, time::Duration};
+
+/// How long to wait on each step of talking to the upstream
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Headers that only apply to one connection, so aren't passed on
+const HOP_BY_HOP: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// The server requests are forwarded to
+pub(crate) struct Upstream {
+    /// The normalised path requests are proxied from
+    prefix: String,
+    /// The `host:port` from the URL, sent as the `Host` header
+    authority: String,
+    /// The path requests are forwarded under, empty for the root
+    base: String,
+}
+
+impl Upstream {
+    /// Forward requests under `prefix` to `url` such as
+    /// `http://127.0.0.1:9000/v1`, panicking for anything but plain `http://`
+    /// URLs
+    pub(crate) fn new(prefix: &str, url: &str) -> Self {
+        let (authority, base) = client::split_url(url).unwrap_or_else(|err| {
+            panic!("only http:// upstreams can be proxied, not {url}: {err}")
+        });
+        Self {
+            prefix: prefix.into(),
+            authority: authority.into(),
+            base: base.trim_end_matches('/').into(),
+        }
+    }
+
+    /// Send `request` on with the prefix swapped for the upstream's path and
+    /// relay the response. A `502 Bad Gateway` is returned if the upstream
+    /// can't be reached or sends something unreadable, and a
+    /// `504 Gateway Timeout` if it takes too long.
+    pub(crate) fn forward(&self, request: &Request) -> Response {
+        self.try_forward(request).unwrap_or_else(|err| {
+            log::warn!("proxying to {} failed: {err}", self.authority);
+            let status_code = match err.kind() {
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => {
+                    StatusCode::GatewayTimeout
+                }
+                _ => StatusCode::BadGateway,
+            };
+            Response::new().status(status_code).text_body(status_code)
+        })
+    }
+
+    fn try_forward(&self, request: &Request) -> io::Result<Response> {
+        let mut stream =
+            client::connect(&self.authority, Some(UPSTREAM_TIMEOUT))?;
+        let mut response = client::exchange(
+            &mut stream,
+            &self.serialise(request),
+            *request.method() == Method::Head,
+        )?;
+
+        let listed = response
+            .header_values("connection")
+            .flat_map(|value| value.split(','))
+            .map(|token| token.trim().to_ascii_lowercase())
+            .collect::<Vec<_>>();
+        response.retain_headers(|name| {
+            !HOP_BY_HOP.contains(&name)
+                && !listed.iter().any(|token| token == name)
+        });
+        Ok(response)
+    }
+
+    /// The request as it is sent upstream, with the path under the upstream's
+    /// base and the headers for this hop replaced
+    fn serialise(&self, request: &Request) -> Vec<u8> {
+        let path = match self.prefix.as_str() {
+            "/" => request.path(),
+            prefix => request.path().strip_prefix(prefix).unwrap_or_default(),
+        };
+        let query = request
+            .request_target()
+            .split_once('?')
+            .map(|(_, query)| format!("?{query}"))
+            .unwrap_or_default();
+        let path = match format!("{}{}", self.base, encode_path(path)) {
+            path if path.is_empty() => "/".into(),
+            path => path,
+        };
+
+        let mut head = format!(
+            "{} {path}{query} HTTP/1.1\r\nHost: {}\r\n",
+            request.method_str(),
+            self.authority
+        );
+        let listed = connection_tokens(request);
+        for (name, values) in request.headers() {
+            let skip = HOP_BY_HOP.contains(&name.as_str())
+                || listed.contains(name)
+                || matches!(
+                    name.as_str(),
+                    "host" | "content-length" | "x-forwarded-for"
+                );
+            if skip {
+                continue;
+            }
+            for value in values {
+                head.push_str(&format!("{name}: {value}\r\n"));
+            }
+        }
+
+        let forwarded_for = request
+            .header_all("x-forwarded-for")
+            .into_iter()
+            .map(str::to_string)
+            .chain(request.peer_addr().map(|addr| addr.ip().to_string()))
+            .collect::<Vec<_>>();
+        if !forwarded_for.is_empty() {
+            head.push_str(&format!(
+                "x-forwarded-for: {}\r\n",
+                forwarded_for.join(", ")
+            ));
+        }
+        let body = request.body_bytes();
+        if !body.is_empty() || request.header("content-length").is_some() {
+            head.push_str(&format!("content-length: {}\r\n", body.len()));
+        }
+        head.push_str("connection: close\r\n\r\n");
+
+        let mut serialised = head.into_bytes();
+        serialised.extend_from_slice(body);
+        serialised
+    }
+}
+
+/// The lowercased header names listed in a request's `Connection` header,
+/// which are also only for this hop
+fn connection_tokens(request: &Request) -> Vec<String> {
+    request
+        .header_all("connection")
+        .iter()
+        .flat_map(|value| value.split(','))
+        .map(|token| token.trim().to_ascii_lowercase())
+        .collect()
+}
+
+/// Percent-encode the bytes of a decoded `path` that can't appear in one as
+/// they are
+fn encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'.'
+            | b'_'
+            | b'~'
+            | b'!'
+            | b'$'
+            | b'&'
+            | b'\''
+            | b'('
+            | b')'
+            | b'*'
+            | b'+'
+            | b','
+            | b';'
+            | b'='
+            | b':'
+            | b'@'
+            | b'/' => encoded.push(byte as char),
+            byte => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_upstream() {
+        let upstream = Upstream::new("/", "http://127.0.0.1:9000");
+        assert_eq!(upstream.authority, "127.0.0.1:9000");
+        assert_eq!(upstream.base, "");
+
+        let upstream = Upstream::new("/", "http://example.com/v1/");
+        assert_eq!(upstream.authority, "example.com");
+        assert_eq!(upstream.base, "/v1");
+    }
+
+    #[test]
+    #[should_panic(expected = "only http:// upstreams")]
+    fn https_upstream() {
+        Upstream::new("/", "https://example.com");
+    }
+
+    #[test]
+    fn serialise_forwarded_request() {
+        let upstream = Upstream::new("/api", "http://backend:9000/v1");
+        let request = Request::from_bytes(
+            b"POST /api/a%20b?x=1 HTTP/1.1\r\nHost: proxy\r\nX-Test: yes\r\nConnection: keep-alive, x-secret\r\nX-Secret: no\r\nContent-Length: 5\r\n\r\nhello",
+        )
+        .unwrap();
+
+        let wire = String::from_utf8(upstream.serialise(&request)).unwrap();
+        assert!(wire.starts_with(
+            "POST /v1/a%20b?x=1 HTTP/1.1\r\nHost: backend:9000\r\n"
+        ));
+        assert!(wire.contains("\r\nx-test: yes\r\n"));
+        assert!(!wire.contains("x-secret"));
+        assert!(!wire.contains("keep-alive"));
+        assert!(!wire.contains("Host: proxy"));
+        assert!(wire.ends_with(
+            "\r\ncontent-length: 5\r\nconnection: close\r\n\r\nhello"
+        ));
+    }
+}
@@ -0,0 +1,123 @@
+//! Token bucket rate limiting by client IP, see
+//! [`crate::ServerBuilder::rate_limit`]
+
+use crate::{Request, Response, StatusCode};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Wrap `handler` so each client can call it at most `max` times per `per`,
+/// for limiting a single route. Requests over the limit get a
+/// `429 Too Many Requests`.
+pub fn limit(
+    max: u32,
+    per: Duration,
+    handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+) -> impl Fn(Request) -> Response + Send + Sync + 'static {
+    let limiter = RateLimiter::new(max, per);
+    move |request| match limiter.check(&request) {
+        Ok(()) => handler(request),
+        Err(response) => response,
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Gives each client a bucket of `max` tokens that refills over `per`, every
+/// request takes one
+pub(crate) struct RateLimiter {
+    max: f64,
+    per: Duration,
+    state: Mutex<State>,
+}
+
+struct State {
+    buckets: HashMap<IpAddr, Bucket>,
+    pruned: Instant,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(max: u32, per: Duration) -> Self {
+        assert!(max > 0, "the rate limit must allow some requests");
+        Self {
+            max: f64::from(max),
+            per,
+            state: Mutex::new(State {
+                buckets: HashMap::new(),
+                pruned: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take a token for the client making `request`, or the `429` to send if
+    /// it has none left. Requests without a client address aren't limited.
+    pub(crate) fn check(&self, request: &Request) -> Result<(), Response> {
+        let Some(ip) = request.client_ip() else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let per_token = self.per.as_secs_f64() / self.max;
+        let mut state =
+            self.state.lock().unwrap_or_else(|err| err.into_inner());
+
+        // A bucket untouched for `per` is full again, the same as no bucket
+        if now.duration_since(state.pruned) >= self.per {
+            let per = self.per;
+            state
+                .buckets
+                .retain(|_, bucket| now.duration_since(bucket.updated) < per);
+            state.pruned = now;
+        }
+
+        let bucket = state.buckets.entry(ip).or_insert(Bucket {
+            tokens: self.max,
+            updated: now,
+        });
+        let refilled =
+            now.duration_since(bucket.updated).as_secs_f64() / per_token;
+        bucket.tokens = (bucket.tokens + refilled).min(self.max);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        let retry_after = ((1.0 - bucket.tokens) * per_token).ceil().max(1.0);
+        Err(Response::new()
+            .status(StatusCode::TooManyRequests)
+            .header("Retry-After", retry_after)
+            .text_body(StatusCode::TooManyRequests))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn request(ip: &str) -> Request {
+        let mut request =
+            Request::from_bytes(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        request
+            .set_peer_addr(Some(format!("{ip}:1234").parse().unwrap()), false);
+        request
+    }
+
+    #[test]
+    fn refill_and_prune() {
+        let limiter = RateLimiter::new(1, Duration::from_millis(50));
+        assert!(limiter.check(&request("10.0.0.1")).is_ok());
+        assert!(limiter.check(&request("10.0.0.1")).is_err());
+        assert!(limiter.check(&request("10.0.0.2")).is_ok());
+
+        thread::sleep(Duration::from_millis(60));
+        assert!(limiter.check(&request("10.0.0.1")).is_ok());
+        assert_eq!(limiter.state.lock().unwrap().buckets.len(), 1);
+    }
+}
@@ -0,0 +1,432 @@
+use crate::{http::normalise_path, HttpError, Method, Request, Response};
+use std::{collections::HashMap, sync::Arc};
+
+pub(crate) type BoxedHandler = Arc<dyn Fn(Request) -> Response + Send + Sync>;
+
+pub(crate) type BoxedTryHandler =
+    Arc<dyn Fn(Request) -> Result<Response, HttpError> + Send + Sync>;
+
+/// A registered handler, see [`Router::route`]
+#[derive(Clone)]
+pub enum Endpoint {
+    Handler(BoxedHandler),
+    TryHandler(BoxedTryHandler),
+}
+
+impl Endpoint {
+    /// Run the handler, a handler that can't fail always returns `Ok`
+    pub fn call(&self, request: Request) -> Result<Response, HttpError> {
+        match self {
+            Self::Handler(handler) => Ok(handler(request)),
+            Self::TryHandler(handler) => handler(request),
+        }
+    }
+}
+
+/// The handlers registered against a single path
+pub(crate) struct Route {
+    /// The path as registered, such as `/users/:id`
+    pub(crate) pattern: String,
+    /// Serves any method without a more specific handler
+    pub(crate) any: Option<Endpoint>,
+    pub(crate) methods: HashMap<Method, Endpoint>,
+    /// Overrides the server's largest accepted body for this path
+    pub(crate) max_body: Option<usize>,
+}
+
+impl Route {
+    fn new(path: &str) -> Self {
+        Self {
+            pattern: path.into(),
+            any: None,
+            methods: HashMap::new(),
+            max_body: None,
+        }
+    }
+
+    /// The handler for `method`. With `auto_head`, `HEAD` requests fall back
+    /// to the `GET` handler and have their body left off when the response
+    /// is written.
+    pub(crate) fn handler(
+        &self,
+        method: &Method,
+        auto_head: bool,
+    ) -> Option<&Endpoint> {
+        self.methods
+            .get(method)
+            .or_else(|| match method {
+                Method::Head if auto_head => self.methods.get(&Method::Get),
+                _ => None,
+            })
+            .or(self.any.as_ref())
+    }
+
+    /// The methods with a handler for this path, including `HEAD` when
+    /// `auto_head` lets the `GET` handler serve it
+    pub(crate) fn methods(
+        &self,
+        auto_head: bool,
+    ) -> impl Iterator<Item = Method> + '_ {
+        let head = (auto_head
+            && self.methods.contains_key(&Method::Get)
+            && !self.methods.contains_key(&Method::Head))
+        .then_some(Method::Head);
+        self.methods.keys().copied().chain(head)
+    }
+}
+
+/// The value of an `Allow` header listing `methods`
+pub(crate) fn allow(methods: impl Iterator<Item = Method>) -> String {
+    let mut methods: Vec<&str> = methods.map(<&str>::from).collect();
+    methods.sort_unstable();
+    methods.dedup();
+    methods.join(", ")
+}
+
+/// One `/` separated part of a registered path
+#[derive(PartialEq, Eq)]
+enum Segment {
+    Static(String),
+    /// `:name` matches any single non-empty segment
+    Param(String),
+    /// `*name` matches everything left in the path
+    Wildcard(String),
+}
+
+impl Segment {
+    fn parse(segment: &str) -> Self {
+        if let Some(name) = segment.strip_prefix(':') {
+            Self::Param(name.into())
+        } else if let Some(name) = segment.strip_prefix('*') {
+            Self::Wildcard(name.into())
+        } else {
+            Self::Static(segment.into())
+        }
+    }
+
+    /// How general the segment is, lower ranks are preferred
+    fn rank(&self) -> u8 {
+        match self {
+            Self::Static(_) => 0,
+            Self::Param(_) => 1,
+            Self::Wildcard(_) => 2,
+        }
+    }
+}
+
+/// Finds the handlers for a request path. Paths without parameters are
+/// matched exactly and always win. Otherwise patterns are compared segment by
+/// segment from the left, preferring static segments over `:param` over
+/// `*wildcard`, so `/users/me` beats `/users/:id` which beats `/users/*rest`
+/// which beats `/:kind/:id`.
+///
+/// A [`crate::ServerBuilder`] routes with one of these, it can also be used
+/// on its own to match requests read some other way.
+pub struct Router {
+    fixed: HashMap<String, Route>,
+    patterns: Vec<(Vec<Segment>, Route)>,
+    /// Serves requests matching no route
+    pub(crate) default: Endpoint,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Router {
+    /// A router without any routes, answering everything with
+    /// `404 Not Found`
+    pub fn new() -> Self {
+        Self {
+            fixed: HashMap::new(),
+            patterns: Vec::new(),
+            default: Endpoint::Handler(Arc::new(crate::not_found)),
+        }
+    }
+
+    /// Serve `path` with `handler` for requests using `method`, see
+    /// [`crate::ServerBuilder::path`] for the syntax of `path`
+    pub fn add(
+        mut self,
+        method: Method,
+        path: &str,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route_mut(path)
+            .methods
+            .insert(method, Endpoint::Handler(Arc::new(handler)));
+        self
+    }
+
+    /// Serve requests matching no route with `handler`
+    pub fn default(
+        mut self,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.default = Endpoint::Handler(Arc::new(handler));
+        self
+    }
+
+    /// The handler for `request`, or the default handler if no route has one
+    /// for its path and method. The parameters captured from the path and
+    /// the matched pattern are stored in `request` for the handler.
+    pub fn route(&self, request: &mut Request) -> &Endpoint {
+        let Some((route, params)) = self.find(request.path()) else {
+            return &self.default;
+        };
+        let Some(handler) = route.handler(request.method(), true) else {
+            return &self.default;
+        };
+        request.set_route(&route.pattern);
+        request.set_params(params);
+        handler
+    }
+
+    /// The route registered for `path`, creating it if needed
+    pub(crate) fn route_mut(&mut self, path: &str) -> &mut Route {
+        assert!(
+            !path.contains('?'),
+            "{path} has a query string, routes only match the path"
+        );
+        let path = normalise_path(path)
+            .unwrap_or_else(|| panic!("{path} climbs above the root"));
+        let path = path.as_str();
+        let segments: Vec<Segment> =
+            path.split('/').map(Segment::parse).collect();
+        assert!(
+            !segments[..segments.len() - 1]
+                .iter()
+                .any(|segment| matches!(segment, Segment::Wildcard(_))),
+            "wildcard segments must be last in {path}"
+        );
+
+        if segments
+            .iter()
+            .all(|segment| matches!(segment, Segment::Static(_)))
+        {
+            return self
+                .fixed
+                .entry(path.into())
+                .or_insert_with(|| Route::new(path));
+        }
+
+        let position = match self
+            .patterns
+            .iter()
+            .position(|(pattern, _)| *pattern == segments)
+        {
+            Some(position) => position,
+            None => {
+                self.patterns.push((segments, Route::new(path)));
+                self.patterns.len() - 1
+            }
+        };
+        &mut self.patterns[position].1
+    }
+
+    /// Every registered route
+    pub(crate) fn routes(&self) -> impl Iterator<Item = &Route> {
+        self.fixed
+            .values()
+            .chain(self.patterns.iter().map(|(_, route)| route))
+    }
+
+    /// The route matching `path` and the parameters captured from it
+    pub(crate) fn find(
+        &self,
+        path: &str,
+    ) -> Option<(&Route, HashMap<String, String>)> {
+        if let Some(route) = self.fixed.get(path) {
+            return Some((route, HashMap::new()));
+        }
+
+        let segments: Vec<&str> = path.split('/').collect();
+        self.patterns
+            .iter()
+            .filter_map(|(pattern, route)| {
+                Self::capture(pattern, &segments)
+                    .map(|params| (pattern, route, params))
+            })
+            .min_by_key(|(pattern, _, _)| {
+                pattern.iter().map(Segment::rank).collect::<Vec<_>>()
+            })
+            .map(|(_, route, params)| (route, params))
+    }
+
+    fn capture(
+        pattern: &[Segment],
+        segments: &[&str],
+    ) -> Option<HashMap<String, String>> {
+        let wildcard = matches!(pattern.last(), Some(Segment::Wildcard(_)));
+        if segments.len() < pattern.len()
+            || (!wildcard && segments.len() != pattern.len())
+        {
+            return None;
+        }
+
+        let mut params = HashMap::new();
+        for (index, expected) in pattern.iter().enumerate() {
+            let segment = segments[index];
+            match expected {
+                Segment::Static(expected) if expected == segment => {}
+                Segment::Param(name) if !segment.is_empty() => {
+                    params.insert(name.clone(), segment.to_string());
+                }
+                Segment::Wildcard(name) => {
+                    let rest = segments[index..].join("/");
+                    if rest.is_empty() {
+                        return None;
+                    }
+                    params.insert(name.clone(), rest);
+                }
+                _ => return None,
+            }
+        }
+        Some(params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handler(_: Request) -> Response {
+        Response::new()
+    }
+
+    fn users(_: Request) -> Response {
+        Response::new().body("users")
+    }
+
+    fn user(request: Request) -> Response {
+        Response::new().body(format!("user {}", request.param("id").unwrap()))
+    }
+
+    fn call(router: &Router, raw: &str) -> String {
+        let mut request = Request::from_bytes(raw.as_bytes()).unwrap();
+        let response = router.route(&mut request).call(request).unwrap();
+        String::from_utf8(response.serialise()).unwrap()
+    }
+
+    fn router(paths: &[&str]) -> Router {
+        let mut router = Router::new();
+        for path in paths {
+            router.route_mut(path).any =
+                Some(Endpoint::Handler(Arc::new(handler)));
+        }
+        router
+    }
+
+    #[test]
+    fn capture_param() {
+        let router = router(&["/users/:id"]);
+
+        let (_, params) = router.find("/users/42").unwrap();
+        assert_eq!(params.get("id").map(String::as_str), Some("42"));
+
+        assert!(router.find("/users").is_none());
+        assert!(router.find("/users/42/posts").is_none());
+    }
+
+    #[test]
+    fn normalise_registered_path() {
+        let router = router(&["//users//:id/", "/groups/./admin/../all"]);
+
+        let (route, params) = router.find("/users/42").unwrap();
+        assert_eq!(route.pattern, "/users/:id");
+        assert_eq!(params.get("id").map(String::as_str), Some("42"));
+
+        let (route, _) = router.find("/groups/all").unwrap();
+        assert_eq!(route.pattern, "/groups/all");
+    }
+
+    #[test]
+    fn static_beats_param() {
+        let router = router(&["/:kind/:id", "/users/:id", "/users/me"]);
+
+        let (_, params) = router.find("/users/me").unwrap();
+        assert!(params.is_empty());
+
+        let (_, params) = router.find("/users/7").unwrap();
+        assert_eq!(params.len(), 1);
+
+        let (_, params) = router.find("/groups/7").unwrap();
+        assert_eq!(params.get("kind").map(String::as_str), Some("groups"));
+    }
+
+    #[test]
+    fn capture_wildcard() {
+        let router = router(&["/static/*path"]);
+
+        let (_, params) = router.find("/static/a/b/c.png").unwrap();
+        assert_eq!(params.get("path").map(String::as_str), Some("a/b/c.png"));
+
+        assert!(router.find("/static").is_none());
+    }
+
+    #[test]
+    fn wildcard_precedence() {
+        let router =
+            router(&["/static/*path", "/static/:file", "/static/logo.png"]);
+
+        let (_, params) = router.find("/static/logo.png").unwrap();
+        assert!(params.is_empty());
+
+        let (_, params) = router.find("/static/site.css").unwrap();
+        assert_eq!(params.get("file").map(String::as_str), Some("site.css"));
+
+        let (_, params) = router.find("/static/css/site.css").unwrap();
+        assert_eq!(
+            params.get("path").map(String::as_str),
+            Some("css/site.css")
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "wildcard segments must be last")]
+    fn wildcard_must_be_last() {
+        router(&["/static/*path/more"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "/x?y=1 has a query string")]
+    fn query_in_pattern() {
+        router(&["/x?y=1"]);
+    }
+
+    #[test]
+    fn route_pattern() {
+        let router = router(&["/", "/users/", "/users/:id"]);
+
+        assert_eq!(router.find("/").unwrap().0.pattern, "/");
+        assert!(router.find("").is_none());
+        assert_eq!(router.find("/users").unwrap().0.pattern, "/users");
+        assert_eq!(router.find("/users/7").unwrap().0.pattern, "/users/:id");
+    }
+
+    #[test]
+    fn route_requests() {
+        let router = Router::new().add(Method::Get, "/users", users).add(
+            Method::Get,
+            "/users/:id",
+            user,
+        );
+
+        assert!(call(&router, "GET /users HTTP/1.1\r\n\r\n")
+            .ends_with("\r\n\r\nusers"));
+        assert!(call(&router, "HEAD /users HTTP/1.1\r\n\r\n")
+            .ends_with("\r\n\r\nusers"));
+        assert!(call(&router, "GET /users/7 HTTP/1.1\r\n\r\n")
+            .ends_with("\r\n\r\nuser 7"));
+        assert!(call(&router, "GET /groups HTTP/1.1\r\n\r\n")
+            .starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(call(&router, "POST /users HTTP/1.1\r\n\r\n")
+            .starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        let router = router.default(|_| Response::new().body("fallback"));
+        assert!(call(&router, "GET /groups HTTP/1.1\r\n\r\n")
+            .ends_with("\r\n\r\nfallback"));
+    }
+}
@@ -0,0 +1,35 @@
+//! Server-sent events, see [`crate::Response::event_stream`]
+
+/// Format an event called `name` carrying `data`, ready to be yielded to
+/// [`crate::Response::event_stream`]. Each line of `data` is sent as its own
+/// `data:` field, browsers join them back together with newlines.
+pub fn event(name: &str, data: &str) -> String {
+    let mut event = String::new();
+    // A line break would end the field early and start another
+    let name: String =
+        name.chars().filter(|c| !matches!(c, '\r' | '\n')).collect();
+    if !name.is_empty() {
+        event.push_str(&format!("event: {name}\n"));
+    }
+    for line in data.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        event.push_str(&format!("data: {line}\n"));
+    }
+    event.push('\n');
+    event
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_events() {
+        assert_eq!(
+            event("greeting", "hello"),
+            "event: greeting\ndata: hello\n\n"
+        );
+        assert_eq!(event("", "a\r\nb\nc"), "data: a\ndata: b\ndata: c\n\n");
+        assert_eq!(event("bad\nname", ""), "event: badname\ndata: \n\n");
+    }
+}
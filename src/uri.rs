@@ -0,0 +1,79 @@
+use crate::{
+    http::{normalise_path, percent_decode, urlencoded_pairs},
+    Error,
+};
+
+/// The path and query a request was sent to, see [`crate::Request::uri`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Uri {
+    path: String,
+    query: Option<String>,
+    query_pairs: Vec<(String, String)>,
+}
+
+impl Uri {
+    /// The decoded, normalised path, the same as [`crate::Request::path`]
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The query string as it was sent, without the `?`. `None` if there was
+    /// no `?`, `Some("")` if nothing followed it.
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
+    /// The decoded names and values in the query, in the order they were
+    /// sent. Names can repeat, as in `?tag=a&tag=b`.
+    pub fn query_pairs(&self) -> &[(String, String)] {
+        &self.query_pairs
+    }
+}
+
+/// Split a request target such as `/users?page=2` into its decoded parts
+pub(crate) fn parse(target: &str) -> Result<Uri, Error> {
+    let (raw_path, query) = match target.split_once('?') {
+        Some((raw_path, query)) => (raw_path, Some(query)),
+        None => (target, None),
+    };
+    let path = percent_decode(raw_path, false)?;
+    let path = normalise_path(&path).ok_or(Error::InvalidPath)?;
+    let query_pairs = match query {
+        Some(query) => urlencoded_pairs(query)?,
+        None => Vec::new(),
+    };
+    Ok(Uri {
+        path,
+        query: query.map(str::to_string),
+        query_pairs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_target() {
+        let uri =
+            parse("/search/./%7Eall?q=rust+lang&tag=a&tag=b%26c").unwrap();
+        assert_eq!(uri.path(), "/search/~all");
+        assert_eq!(uri.query(), Some("q=rust+lang&tag=a&tag=b%26c"));
+        assert_eq!(
+            uri.query_pairs(),
+            [
+                ("q".into(), "rust lang".into()),
+                ("tag".into(), "a".into()),
+                ("tag".into(), "b&c".into()),
+            ]
+        );
+
+        let uri = parse("/users").unwrap();
+        assert_eq!(uri.query(), None);
+        assert!(uri.query_pairs().is_empty());
+        assert_eq!(parse("/users?").unwrap().query(), Some(""));
+
+        assert_eq!(parse("/a?b=%zz"), Err(Error::Malformed));
+        assert_eq!(parse("/../etc"), Err(Error::InvalidPath));
+    }
+}
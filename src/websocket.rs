@@ -0,0 +1,201 @@
+//! The WebSocket opening handshake, see
+//! <https://www.rfc-editor.org/rfc/rfc6455#section-4.2>. Framing the messages
+//! sent over the upgraded connection is left to the handler.
+
+use crate::{Method, Protocol, Request, Response, StatusCode};
+use std::io::{Read, Write};
+
+/// Appended to the client's key before hashing, from RFC 6455
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The connection handed to a handler once it has been upgraded
+pub trait Stream: Read + Write {}
+
+impl<T: Read + Write> Stream for T {}
+
+/// Accept the WebSocket handshake in `request` with a `101 Switching
+/// Protocols`, after which the server calls `handler` with the connection
+/// and closes it once `handler` returns. The server's read and write
+/// timeouts still apply. A request that isn't a valid version 13 handshake
+/// gets a `400 Bad Request` instead.
+pub fn upgrade(
+    request: &Request,
+    handler: impl FnOnce(&mut dyn Stream) + Send + 'static,
+) -> Response {
+    let has_token = |name: &str, token: &str| {
+        request.header_all(name).iter().any(|value| {
+            value
+                .split(',')
+                .any(|part| part.trim().eq_ignore_ascii_case(token))
+        })
+    };
+    let handshake = *request.method() == Method::Get
+        && *request.protocol() == Protocol::Http1_1
+        && has_token("upgrade", "websocket")
+        && has_token("connection", "upgrade");
+    let key = request.header("sec-websocket-key").filter(|key| {
+        crate::auth::decode_base64(key).is_some_and(|nonce| nonce.len() == 16)
+    });
+    let (true, Some(key)) = (handshake, key) else {
+        return Response::new()
+            .status(StatusCode::BadRequest)
+            .text_body(StatusCode::BadRequest);
+    };
+    if request.header("sec-websocket-version") != Some("13") {
+        return Response::new()
+            .status(StatusCode::BadRequest)
+            .header("Sec-WebSocket-Version", "13")
+            .text_body(StatusCode::BadRequest);
+    }
+
+    Response::new()
+        .status(StatusCode::SwitchingProtocols)
+        .header("Upgrade", "websocket")
+        .header("Connection", "Upgrade")
+        .header("Sec-WebSocket-Accept", accept_key(key))
+        .on_upgrade(handler)
+}
+
+/// The `Sec-WebSocket-Accept` proving the server read the client's `key`
+pub fn accept_key(key: &str) -> String {
+    encode_base64(&sha1(format!("{}{GUID}", key.trim()).as_bytes()))
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [
+        0x6745_2301,
+        0xefcd_ab89,
+        0x98ba_dcfe,
+        0x1032_5476,
+        0xc3d2_e1f0,
+    ];
+
+    // Pad to a whole number of 64 byte blocks, ending with the bit length
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_be_bytes(bytes.try_into().unwrap());
+        }
+        for i in 16..80 {
+            words[i] =
+                (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16])
+                    .rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in words.into_iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ed9_eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+                _ => (b ^ c ^ d, 0xca62_c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in state.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, word) in digest.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+/// Encode `data` as padded base64 using the standard alphabet
+fn encode_base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| {
+            bits | u32::from(byte) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let sextet = bits >> (18 - 6 * i) & 0x3f;
+                encoded.push(char::from(ALPHABET[sextet as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    #[test]
+    fn hash() {
+        assert_eq!(hex(&sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(
+            hex(&sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(
+            hex(&sha1(&[b'a'; 1000])),
+            "291e9a6c66994949b57ba5e650361e98fc36b1ba"
+        );
+    }
+
+    #[test]
+    fn encode() {
+        assert_eq!(encode_base64(b""), "");
+        assert_eq!(encode_base64(b"f"), "Zg==");
+        assert_eq!(encode_base64(b"fo"), "Zm8=");
+        assert_eq!(encode_base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn rfc_accept_key() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn reject_invalid_handshakes() {
+        for raw in [
+            "GET /ws HTTP/1.1\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            "GET /ws HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: c2hvcnQ=\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            "POST /ws HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        ] {
+            let request = Request::from_bytes(raw.as_bytes()).unwrap();
+            let response = upgrade(&request, |_| {});
+            assert!(String::from_utf8(response.serialise())
+                .unwrap()
+                .starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        }
+
+        let request = Request::from_bytes(b"GET /ws HTTP/1.1\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 8\r\n\r\n").unwrap();
+        let response = upgrade(&request, |_| {});
+        assert_eq!(response.header_value("sec-websocket-version"), Some("13"));
+    }
+}